@@ -0,0 +1,115 @@
+use {anyhow::bail, std::fmt::Write};
+
+/// Computations of C-instructions, indexed by the `c` bits, with the `a` bit clear and set
+static COMPUTATIONS: &[(u16, &str, Option<&str>)] = &[
+    (0b101010, "0", None),
+    (0b111111, "1", None),
+    (0b111010, "-1", None),
+    (0b001100, "D", None),
+    (0b110000, "A", Some("M")),
+    (0b001101, "!D", None),
+    (0b110001, "!A", Some("!M")),
+    (0b001111, "-D", None),
+    (0b110011, "-A", Some("-M")),
+    (0b011111, "D+1", None),
+    (0b110111, "A+1", Some("M+1")),
+    (0b001110, "D-1", None),
+    (0b110010, "A-1", Some("M-1")),
+    (0b000010, "D+A", Some("D+M")),
+    (0b010011, "D-A", Some("D-M")),
+    (0b000111, "A-D", Some("M-D")),
+    (0b000000, "D&A", Some("D&M")),
+    (0b010101, "D|A", Some("D|M")),
+];
+static DESTINATIONS: [&str; 8] = ["", "M=", "D=", "MD=", "A=", "AM=", "AD=", "AMD="];
+static JUMPS: [&str; 8] = ["", ";JGT", ";JEQ", ";JGE", ";JLT", ";JNE", ";JLE", ";JMP"];
+
+/// Translates Hack machine code back into assembly
+///
+/// # Arguments
+/// * `machine_code` - Contents of a `.hack` file, one instruction of 16 binary digits per line
+///
+/// # Returns
+/// Returns the assembly without symbols, so it assembles into the same program
+pub fn disassemble(machine_code: &str) -> anyhow::Result<String> {
+    let mut assembly = String::new();
+
+    for (line_number, line) in machine_code.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let instruction = match u16::from_str_radix(line, 2) {
+            Ok(instruction) if line.len() == 16 => instruction,
+            _ => bail!("line {}: invalid instruction '{line}'", line_number + 1),
+        };
+
+        if instruction & 0x8000 == 0 {
+            writeln!(assembly, "@{instruction}")?;
+            continue;
+        }
+        let a = instruction & 0x1000 != 0;
+        let c = instruction >> 6 & 0b111111;
+        let comp = COMPUTATIONS
+            .iter()
+            .find(|&&(bits, _, _)| bits == c)
+            .and_then(|&(_, a_clear, a_set)| if a { a_set } else { Some(a_clear) });
+        let Some(comp) = comp.filter(|_| instruction & 0x6000 == 0x6000) else {
+            bail!("line {}: invalid instruction '{line}'", line_number + 1);
+        };
+        let dest = DESTINATIONS[(instruction >> 3 & 0b111) as usize];
+        let jump = JUMPS[(instruction & 0b111) as usize];
+        writeln!(assembly, "{dest}{comp}{jump}")?;
+    }
+
+    Ok(assembly)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble_source;
+
+    #[test]
+    fn instructions() -> anyhow::Result<()> {
+        let machine_code = "\
+            0000000000000010\n\
+            1110110000010000\n\
+            0000000000000011\n\
+            1110000010010000\n\
+            \n\
+            0000000000000000\n\
+            1110001100001000\n\
+            1111110111011000\n\
+            1110101010000111\n";
+        assert_eq!(
+            disassemble(machine_code)?,
+            "@2\nD=A\n@3\nD=D+A\n@0\nM=D\nMD=M+1\n0;JMP\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn assembles_like_symbols() -> anyhow::Result<()> {
+        let machine_code = "\
+            0000000000010000\n\
+            1111110111001000\n\
+            0000000000000000\n\
+            1110101010000111\n";
+        assert_eq!(
+            assemble_source(&disassemble(machine_code)?)?,
+            assemble_source("(LOOP)\n@i\nM=M+1\n@LOOP\n0;JMP\n")?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(disassemble("0000000000000010\n111011000001000\n").is_err());
+        assert!(disassemble("000000000000002\n").is_err());
+        // The two unused bits of C-instructions are not set
+        assert!(disassemble("1000110000010000\n").is_err());
+        // There is no computation `-1` using M
+        assert!(disassemble("1111111010010000\n").is_err());
+    }
+}
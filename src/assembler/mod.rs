@@ -1,12 +1,18 @@
-use brainhack::traits::binary::Binary;
+mod machine;
+mod parser;
+
+pub use machine::disassemble;
+pub use parser::{HackPair, HackParser, Rule};
+
+use crate::traits::binary::Binary;
 
 use {
-    crate::parser::{HackPair, Rule},
+    crate::prelude::*,
     anyhow::{anyhow, bail},
-    brainhack::prelude::*,
     itertools::{chain, Itertools},
+    pest::Parser as _,
     std::{
-        collections::{hash_map::Entry, HashMap},
+        collections::{hash_map::Entry, HashMap, HashSet},
         io::Write,
     },
 };
@@ -19,6 +25,8 @@ pub struct SymbolData {
 
 type SymbolTable = HashMap<String, SymbolData>;
 
+/// Value of P that ends the program
+const TERMINATE: u16 = u16::MAX;
 const RESERVED_REGISTERS: usize = 16;
 const ADDRESS_SPACE_SIZE: usize = 32768;
 static KEYWORDS: &[(&str, usize)] = &[
@@ -31,9 +39,25 @@ static KEYWORDS: &[(&str, usize)] = &[
     ("KBD", 24576),
 ];
 
+/// Assembles Hack assembly into brainfuck code in memory
+///
+/// # Arguments
+/// * `source` - Contents of a `.asm` file
+///
+/// # Returns
+/// Returns the brainfuck code
+pub fn assemble_source(source: &str) -> anyhow::Result<Vec<u8>> {
+    let ast = HackParser::parse(Rule::file, source)?
+        .exactly_one()
+        .expect("multiple pairs matching Rule::file");
+    assemble(ast, vec![])
+}
+
 pub fn assemble<W: Write>(file: HackPair, out: W) -> anyhow::Result<W> {
     let mut coder = Coder::new(out);
     let symbol_table = scan_symbols(file.clone())?;
+    let halts = scan_halts(file.clone(), &symbol_table);
+    let mut address = 0;
 
     coder.while_cond(
         pos::FU,
@@ -73,6 +97,8 @@ pub fn assemble<W: Write>(file: HackPair, out: W) -> anyhow::Result<W> {
                         };
                         let value = u16::try_from(value)
                             .map_err(|_| anyhow!("invalid constant '{}'", spec))?;
+                        let halt = halts.contains(&address);
+                        address += 1;
 
                         c.inc_word(word::Q, [pos::VU, pos::VL])?
                             .is_nonzero(word::Q, pos::FU, [pos::VU, pos::VL])?
@@ -82,13 +108,21 @@ pub fn assemble<W: Write>(file: HackPair, out: W) -> anyhow::Result<W> {
                                 |c| {
                                     c.dec_word(word::Q, [pos::VU, pos::VL])?
                                         .is_zero(word::Q, pos::FU, [pos::VU, pos::VL])?
-                                        .if_move(pos::FU, |c| c.set_word(word::A, value)?.seek(5))
+                                        .if_move(pos::FU, |c| {
+                                            c.set_word(word::A, value)?;
+                                            if halt {
+                                                // P is incremented after the last line
+                                                set_terminate(c, 1)?;
+                                            }
+                                            c.seek(5)
+                                        })
                                 },
                                 |c| c.dec_word(word::Q, [pos::VU, pos::VL]),
                             )?
                             .dec_word(word::Q, [pos::VU, pos::VL])?;
                     }
                     Rule::c_instruction => {
+                        address += 1;
                         let mut dest = "";
                         let mut comp = "";
                         let mut jump = "";
@@ -561,13 +595,7 @@ pub fn assemble<W: Write>(file: HackPair, out: W) -> anyhow::Result<W> {
                         return c.is_zero_move(word::Q, pos::FU, pos::VU)?.if_else_move(
                             pos::FU,
                             pos::FL,
-                            |c| {
-                                c.clear_cell(&[pos::PU, pos::PL])?
-                                    .seek(pos::PU)?
-                                    .dec_val()?
-                                    .seek(pos::PL)?
-                                    .dec_val()
-                            },
+                            |c| set_terminate(c, 0),
                             |c| c.inc_word(word::P, [pos::VU, pos::VL]),
                         )
                     }
@@ -582,6 +610,64 @@ pub fn assemble<W: Write>(file: HackPair, out: W) -> anyhow::Result<W> {
     Ok(coder.into_writer())
 }
 
+/// Sets P so that it holds `TERMINATE` once it was incremented a number of times, the program
+/// then ends before running another line
+///
+/// # Arguments
+/// * `c` - Coder writing the program
+/// * `increments` - Number of times P is incremented before the main loop checks it
+fn set_terminate<W: Write>(c: &mut Coder<W>, increments: u8) -> anyhow::Result<&mut Coder<W>> {
+    let [upper, lower] = TERMINATE.wrapping_sub(increments.into()).to_be_bytes();
+    // Values close to `TERMINATE` take fewer instructions to reach by decrementing
+    c.clear_cell(&[pos::PU, pos::PL])?
+        .seek(pos::PU)?
+        .dec_val_by(upper.wrapping_neg())?
+        .seek(pos::PL)?
+        .dec_val_by(lower.wrapping_neg())
+}
+
+/// Finds the halt loops of a program, such as `(END) @END 0;JMP`
+///
+/// # Returns
+/// Returns the addresses of A-instructions that load their own address and are followed by an
+/// unconditional jump that writes no register, so the program stops changing once they run
+fn scan_halts(file: HackPair, symbol_table: &SymbolTable) -> HashSet<usize> {
+    let instructions = file
+        .into_inner()
+        .filter(|line| matches!(line.as_rule(), Rule::a_instruction | Rule::c_instruction))
+        .collect_vec();
+
+    instructions
+        .into_iter()
+        .tuple_windows()
+        .enumerate()
+        .filter(|(address, (a_instruction, c_instruction))| {
+            if a_instruction.as_rule() != Rule::a_instruction
+                || c_instruction.as_rule() != Rule::c_instruction
+            {
+                return false;
+            }
+            let spec = a_instruction.clone().into_inner().exactly_one().unwrap();
+            let value = match spec.as_rule() {
+                Rule::constant => spec.as_str().parse().ok(),
+                Rule::symbol => symbol_table.get(spec.as_str()).map(|data| data.value),
+                _ => unreachable!(),
+            };
+
+            let mut unconditional_jump = false;
+            for spec in c_instruction.clone().into_inner() {
+                match spec.as_rule() {
+                    Rule::dest => return false,
+                    Rule::jump => unconditional_jump = spec.as_str() == "JMP",
+                    _ => {}
+                }
+            }
+            value == Some(*address) && unconditional_jump
+        })
+        .map(|(address, _)| address)
+        .collect()
+}
+
 pub fn scan_symbols(file: HackPair) -> anyhow::Result<SymbolTable> {
     let mut symbol_table: SymbolTable = (0..RESERVED_REGISTERS)
         .map(|i| {
@@ -677,3 +763,47 @@ pub fn scan_symbols(file: HackPair) -> anyhow::Result<SymbolTable> {
 
     unreachable!()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hackfuck::{Exit, Interpreter, Limits, Parser};
+
+    fn halts(source: &str) -> HashSet<usize> {
+        let file = HackParser::parse(Rule::file, source)
+            .unwrap()
+            .exactly_one()
+            .unwrap();
+        let symbol_table = scan_symbols(file.clone()).unwrap();
+        scan_halts(file, &symbol_table)
+    }
+
+    #[test]
+    fn halt_loops() {
+        assert_eq!(halts("(END)\n@END\n0;JMP\n"), HashSet::from([0]));
+        assert_eq!(halts("@2\nD=A\n@2\nD;JMP\n"), HashSet::from([2]));
+        assert_eq!(
+            halts("D=A\n(END)\n@END\n(AGAIN)\n0;JMP\n"),
+            HashSet::from([1])
+        );
+        // Loops that can end or keep changing registers
+        assert!(halts("(LOOP)\n@LOOP\nD;JGT\n").is_empty());
+        assert!(halts("(LOOP)\n@LOOP\nD=D+1;JMP\n").is_empty());
+        assert!(halts("D=A\n@0\n0;JMP\n").is_empty());
+    }
+
+    #[test]
+    fn halt_terminates() -> anyhow::Result<()> {
+        let code = assemble_source("@7\nD=A\n@0\nM=D\n(END)\n@END\n0;JMP\n")?;
+        let instructions = Parser::from_bytes(&code)?.optimized_parse(false);
+
+        let mut interpreter = Interpreter::new(instructions);
+        let limits = Limits {
+            max_steps: Some(1_000_000),
+            timeout: None,
+        };
+        assert_eq!(interpreter.eval_limited(limits)?, Exit::Halted);
+        assert_eq!(interpreter.tape.get_word(0), Some(7));
+        Ok(())
+    }
+}
@@ -1,5 +1,7 @@
+use pest_derive::Parser;
+
 #[derive(Parser)]
-#[grammar = "bin/assembler/hack.pest"]
+#[grammar = "assembler/hack.pest"]
 pub struct HackParser;
 
 pub type HackPair<'i> = pest::iterators::Pair<'i, Rule>;
@@ -0,0 +1,5 @@
+use {brainhack::cli::Cli, clap::Parser as _};
+
+fn main() -> anyhow::Result<()> {
+    Cli::parse().command.run()
+}
@@ -1,6 +1,17 @@
-use {clap::Parser, std::path::PathBuf};
+use {
+    brainhack::cli::{Emit, RunArgs},
+    clap::Parser,
+};
 
+/// Runs brainfuck programs, or prints them optimized with `--dump-ir` or `--emit`
 #[derive(Debug, Parser)]
 pub struct Cli {
-    pub file: PathBuf,
+    #[command(flatten)]
+    pub run: RunArgs,
+    /// Print the optimized program instead of running it
+    #[arg(long)]
+    pub dump_ir: bool,
+    /// Print the optimized program in another language instead of running it
+    #[arg(long, value_enum)]
+    pub emit: Option<Emit>,
 }
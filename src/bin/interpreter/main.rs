@@ -1,18 +1,27 @@
 mod cli;
 
 use {
-    brainhack::hackfuck::{Interpreter, Parser},
+    brainhack::cli::{self as commands, Emit, OutputArgs, VmArgs},
     clap::Parser as _,
     cli::Cli,
 };
 
-fn main() {
+/// Same as `brainhack debug`, or `brainhack vm` with `--dump-ir` or `--emit`
+fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
-    let source_path = &cli.file;
 
-    let program = Parser::from_file(source_path)
-        .unwrap()
-        .optimized_parse(true);
-    let mut interpreter = Interpreter::new(program).init_screen();
-    interpreter.run().unwrap();
+    let emit = if cli.dump_ir {
+        Some(Emit::Ir)
+    } else {
+        cli.emit
+    };
+    match emit {
+        Some(emit) => commands::vm(VmArgs {
+            source: cli.run.source,
+            opt: cli.run.opt,
+            emit,
+            output: OutputArgs { out: None },
+        }),
+        None => commands::debug(cli.run),
+    }
 }
@@ -0,0 +1,322 @@
+use super::{
+    AsmArgs, Backend, DisasmArgs, Emit, KeyArgs, OptArgs, RunArgs, ScreenshotArgs, SourceArgs,
+    TestArgs, VmArgs,
+};
+use crate::assembler::{assemble_source, disassemble};
+#[cfg(feature = "sdl")]
+use crate::hackfuck::Window;
+use crate::hackfuck::{
+    dump_ir, emit_bf, emit_c, emit_wat, Exit, Frontend, Instruction, Interpreter, IrCache,
+    KeyScript, Observer, Parser, Screen, Tape, Terminal,
+};
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Comment of recorded key scripts giving the optimizer settings their steps were counted with
+const OPTIMIZER_HEADER: &str = "# Optimized with ";
+
+/// Assembles a `.asm` or `.hack` file into brainfuck code
+pub fn asm(args: AsmArgs) -> Result<()> {
+    let code = assemble_file(&args.file)?;
+
+    let out = match args.output.out {
+        Some(path) => Some(path),
+        None if !args.stdout && is_hack(&args.file) => Some(args.file.with_extension("bf")),
+        None => None,
+    };
+    write_output(out.as_deref(), &code)
+}
+
+/// Translates a `.hack` file into assembly
+pub fn disasm(args: DisasmArgs) -> Result<()> {
+    let assembly = disassemble(&read_to_string(&args.file)?)?;
+    write_output(args.output.out.as_deref(), assembly.as_bytes())
+}
+
+/// Runs a program until it finishes or reaches a limit, breakpoints are removed
+pub fn run(args: RunArgs) -> Result<()> {
+    execute(args, false)
+}
+
+/// Runs a program until it finishes or reaches a limit, pausing at breakpoints
+pub fn debug(args: RunArgs) -> Result<()> {
+    execute(args, true)
+}
+
+/// Prints the optimized program in another language
+pub fn vm(args: VmArgs) -> Result<()> {
+    let (program, _) = load_program(&args.source, &args.opt, false)?;
+
+    let code = match args.emit {
+        Emit::Ir => {
+            let mut listing = vec![];
+            dump_ir(&program, &mut listing)?;
+            listing
+        }
+        Emit::Bf => emit_bf(&program),
+        Emit::C => emit_c(&program).into_bytes(),
+        Emit::Wat => emit_wat(&program).into_bytes(),
+    };
+    write_output(args.output.out.as_deref(), &code)
+}
+
+/// Runs a program without IO and prints words of the Hack memory
+pub fn test(args: TestArgs) -> Result<()> {
+    let (program, cache) = load_program(&args.source, &args.opt, false)?;
+
+    let mut observer = screenshot_observer(&args.screenshots);
+    let mut interpreter = Interpreter::new(program);
+    load_keys(&mut interpreter, &args.keys, &cache)?;
+    let exit = interpreter.eval_observed(args.limits.limits(), observer.as_mut())?;
+    save_final_screenshot(&args.screenshots, &interpreter.tape)?;
+    println!("{exit}");
+
+    for address in args.ram {
+        let word = interpreter
+            .tape
+            .get_word(address as usize)
+            .expect("addresses are checked while parsing the options");
+        println!("RAM[{address}] = {}", word as i16);
+    }
+    Ok(())
+}
+
+fn execute(args: RunArgs, debug: bool) -> Result<()> {
+    // Breakpoints print the registers and wait for enter on the terminal the screen is drawn in
+    if debug && args.terminal {
+        anyhow::bail!("Breakpoints need the terminal, debug programs in a window or headless");
+    }
+    let (program, cache) = load_program(&args.source, &args.opt, debug)?;
+    let limits = args.limits.limits();
+    let mut observer = screenshot_observer(&args.screenshots);
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    if matches!(args.backend, Backend::Jit) && observer.is_some() {
+        anyhow::bail!("Compiled programs can't count executed instructions for screenshots");
+    }
+
+    let mut interpreter = Interpreter::new(program);
+    load_keys(&mut interpreter, &args.keys, &cache)?;
+    interpreter.set_fps(args.fps);
+    if args.record.is_some() {
+        interpreter.record_keys();
+    }
+
+    let exit = if args.headless {
+        let exit = match args.backend {
+            Backend::Interpreter => interpreter.eval_observed(limits, observer.as_mut())?,
+            #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+            Backend::Jit => interpreter.eval_jit(limits)?,
+        };
+        save_final_screenshot(&args.screenshots, &interpreter.tape)?;
+        exit
+    } else {
+        let mut frontend = open_frontend(&args)?;
+        let result = match args.backend {
+            Backend::Interpreter => interpreter.run(frontend.as_mut(), limits, observer.as_mut()),
+            #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+            Backend::Jit => interpreter.run_jit(frontend.as_mut(), limits),
+        };
+        let result = match result {
+            Ok(Exit::Halted) if !args.exit_on_halt => interpreter
+                .wait_for_quit(frontend.as_mut())
+                .map(|()| Exit::Halted),
+            result => result,
+        };
+        // Closes the window and restores the terminal before printing anything
+        drop(frontend);
+        // Saved before returning errors, the sessions that end in one are the most worth replaying
+        save_recording(&args, &interpreter, &cache)?;
+        let exit = result?;
+        save_final_screenshot(&args.screenshots, &interpreter.tape)?;
+        exit
+    };
+    eprintln!("{exit}");
+    Ok(())
+}
+
+/// Opens the terminal or the window the program runs in
+fn open_frontend(args: &RunArgs) -> Result<Box<dyn Frontend>> {
+    if args.terminal {
+        Ok(Box::new(Terminal::open(args.glyphs)?))
+    } else {
+        open_window(args)
+    }
+}
+
+#[cfg(feature = "sdl")]
+fn open_window(args: &RunArgs) -> Result<Box<dyn Frontend>> {
+    Ok(Box::new(Window::open(&args.window.options())?))
+}
+
+#[cfg(not(feature = "sdl"))]
+fn open_window(_args: &RunArgs) -> Result<Box<dyn Frontend>> {
+    anyhow::bail!("Built without the `sdl` feature, add --headless or --terminal to run programs")
+}
+
+/// Reads and optimizes a program, going through the cache next to it
+///
+/// # Arguments
+/// * `source` - Program to load
+/// * `opt` - Passes to perform and whether to use the cache
+/// * `debug` - Breakpoints are kept if set
+///
+/// # Returns
+/// Returns the optimized program and its cache, which identifies the optimizer settings
+fn load_program(
+    source: &SourceArgs,
+    opt: &OptArgs,
+    debug: bool,
+) -> Result<(Vec<Instruction>, IrCache)> {
+    let code = if is_hack(&source.file) {
+        let code = assemble_file(&source.file)?;
+        if source.keep_bf {
+            fs::write(source.file.with_extension("bf"), &code)?;
+        }
+        code
+    } else {
+        fs::read(&source.file).with_context(|| format!("cannot read {}", source.file.display()))?
+    };
+
+    let passes = opt.pass_manager(debug);
+    let cache = IrCache::new(&source.file, &code, &passes);
+
+    // Statistics are only collected while the passes run
+    if !opt.no_cache && !opt.opt_stats {
+        match cache.load() {
+            Ok(Some(program)) => return Ok((program, cache)),
+            Ok(None) => {}
+            Err(err) => eprintln!("Ignoring {}: {err}", cache.path().display()),
+        }
+    }
+
+    let parser = Parser::from_bytes(&code)?;
+    let program = if opt.opt_stats {
+        let (program, stats) = passes.run_with_stats(parser);
+        for pass_stats in stats {
+            eprintln!("{pass_stats}");
+        }
+        program
+    } else {
+        passes.run(parser)
+    };
+
+    if !opt.no_cache {
+        if let Err(err) = cache.store(&program) {
+            eprintln!("Cannot write {}: {err}", cache.path().display());
+        }
+    }
+    Ok((program, cache))
+}
+
+/// Saves the changes of KBD recorded while the program ran, if requested
+///
+/// The steps count optimized instructions, so the optimizer settings are written along
+fn save_recording(args: &RunArgs, interpreter: &Interpreter, cache: &IrCache) -> Result<()> {
+    if let (Some(path), Some(script)) = (&args.record, interpreter.recorded_keys()) {
+        let recording = format!(
+            "# Keys pressed while running {}\n{OPTIMIZER_HEADER}{}\n{script}",
+            args.source.file.display(),
+            cache.settings()
+        );
+        fs::write(path, recording).with_context(|| format!("cannot write {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Reads the key script to play while the program runs, if given
+///
+/// Warns if the script was recorded with another program or other optimizer settings, its steps
+/// then fall on other instructions
+fn load_keys(interpreter: &mut Interpreter, args: &KeyArgs, cache: &IrCache) -> Result<()> {
+    if let Some(path) = &args.keys {
+        let text = read_to_string(path)?;
+        let script = KeyScript::parse(&text)
+            .with_context(|| format!("invalid key script {}", path.display()))?;
+        let settings = cache.settings();
+        let recorded = text
+            .lines()
+            .find_map(|line| line.strip_prefix(OPTIMIZER_HEADER));
+        if let Some(recorded) = recorded.filter(|&recorded| recorded != settings) {
+            eprintln!(
+                "Warning: {} was recorded with {recorded} but runs with {settings}, \
+                 the keys may change at other points of the program",
+                path.display()
+            );
+        }
+        interpreter.set_keys(&script);
+    }
+    Ok(())
+}
+
+/// Returns an observer saving the screen every few instructions, if requested
+fn screenshot_observer(args: &ScreenshotArgs) -> Option<Observer<'_>> {
+    let every = args.screenshot_every?;
+    let path = args.screenshot_at_exit.as_deref()?;
+    Some(Observer::new(every, move |tape, steps| {
+        save_screenshot(tape.screen(), &step_path(path, steps))
+    }))
+}
+
+fn save_final_screenshot(args: &ScreenshotArgs, tape: &Tape) -> Result<()> {
+    match &args.screenshot_at_exit {
+        Some(path) => save_screenshot(tape.screen(), path),
+        None => Ok(()),
+    }
+}
+
+/// Saves the screen as PNG, text or PBM depending on the extension of the path
+fn save_screenshot(screen: Screen, path: &Path) -> Result<()> {
+    let image = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("png") => screen.to_png()?,
+        Some("txt") => screen.to_ascii().into_bytes(),
+        _ => screen.to_pbm(),
+    };
+    fs::write(path, image).with_context(|| format!("cannot write {}", path.display()))
+}
+
+/// Adds a step count to the name of a file, `screen.png` becomes `screen-1000.png`
+fn step_path(path: &Path, steps: usize) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push(format!("-{steps}"));
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name)
+}
+
+/// Whether a file holds Hack assembly or machine code rather than brainfuck
+fn is_hack(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|ext| ext.to_str()),
+        Some("asm" | "hack")
+    )
+}
+
+/// Assembles a `.asm` file, or a `.hack` file after translating it into assembly
+fn assemble_file(path: &Path) -> Result<Vec<u8>> {
+    let source = read_to_string(path)?;
+    let assembly = if path.extension() == Some("hack".as_ref()) {
+        disassemble(&source)?
+    } else {
+        source
+    };
+    assemble_source(&assembly).with_context(|| format!("cannot assemble {}", path.display()))
+}
+
+fn read_to_string(path: &Path) -> Result<String> {
+    fs::read_to_string(path).with_context(|| format!("cannot read {}", path.display()))
+}
+
+/// Writes to a file, or to the standard output without a path
+fn write_output(path: Option<&Path>, bytes: &[u8]) -> Result<()> {
+    match path {
+        Some(path) => {
+            fs::write(path, bytes).with_context(|| format!("cannot write {}", path.display()))
+        }
+        None => Ok(io::stdout().lock().write_all(bytes)?),
+    }
+}
@@ -0,0 +1,307 @@
+mod commands;
+
+pub use commands::{asm, debug, disasm, run, test, vm};
+
+#[cfg(feature = "sdl")]
+use crate::hackfuck::{Rgb, WindowOptions};
+use {
+    crate::hackfuck::{Glyphs, Limits, Pass, PassManager, DEFAULT_FPS},
+    clap::{Args, Parser, Subcommand, ValueEnum},
+    std::{path::PathBuf, time::Duration},
+};
+
+/// Assembles Hack programs into brainfuck and runs them
+#[derive(Debug, Parser)]
+#[command(name = "brainhack")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Assemble Hack assembly or machine code into brainfuck code
+    Asm(AsmArgs),
+    /// Translate Hack machine code back into assembly, printed to standard output
+    Disasm(DisasmArgs),
+    /// Run a program, drawing the screen in a window unless it runs headless
+    Run(RunArgs),
+    /// Run a program like `run`, pausing at every `#` to print the registers
+    Debug(RunArgs),
+    /// Print the optimized program for another machine to standard output
+    Vm(VmArgs),
+    /// Run a program without a window and print words of its memory
+    Test(TestArgs),
+}
+
+impl Command {
+    /// Performs the subcommand
+    pub fn run(self) -> anyhow::Result<()> {
+        match self {
+            Command::Asm(args) => asm(args),
+            Command::Disasm(args) => disasm(args),
+            Command::Run(args) => run(args),
+            Command::Debug(args) => debug(args),
+            Command::Vm(args) => vm(args),
+            Command::Test(args) => test(args),
+        }
+    }
+}
+
+/// Program to load, assembling Hack programs in memory
+#[derive(Debug, Args)]
+pub struct SourceArgs {
+    /// Brainfuck code, or Hack assembly (`.asm`) or machine code (`.hack`) to assemble first
+    pub file: PathBuf,
+    /// Save the brainfuck code assembled from `.asm` or `.hack` files next to them
+    #[arg(long)]
+    pub keep_bf: bool,
+}
+
+/// How the program is optimized before it runs
+#[derive(Debug, Args)]
+pub struct OptArgs {
+    /// Optimization level, 0 runs the program as is and 3 performs every default pass
+    #[arg(
+        short = 'O',
+        long,
+        default_value_t = 3,
+        value_parser = clap::value_parser!(u8).range(0..=3)
+    )]
+    pub opt_level: u8,
+    /// Comma separated passes to perform in order, replaces the optimization level
+    #[arg(long, value_delimiter = ',')]
+    pub passes: Option<Vec<Pass>>,
+    /// Print how many instructions each pass removed or rewrote
+    #[arg(long)]
+    pub opt_stats: bool,
+    /// Always parse and optimize the program instead of reading the cache next to it
+    #[arg(long)]
+    pub no_cache: bool,
+}
+
+impl OptArgs {
+    /// Returns the passes to perform
+    ///
+    /// # Arguments
+    /// * `debug` - Breakpoints are kept if set
+    pub fn pass_manager(&self, debug: bool) -> PassManager {
+        match &self.passes {
+            Some(passes) => PassManager::from_passes(passes, debug),
+            None => PassManager::new(self.opt_level, debug),
+        }
+    }
+}
+
+/// When a running program is stopped
+#[derive(Debug, Args)]
+pub struct LimitArgs {
+    /// Stop after executing this many instructions
+    #[arg(long)]
+    pub max_steps: Option<usize>,
+    /// Stop after running for this many seconds
+    #[arg(long, value_parser = parse_seconds)]
+    pub timeout: Option<Duration>,
+}
+
+impl LimitArgs {
+    pub fn limits(&self) -> Limits {
+        Limits {
+            max_steps: self.max_steps,
+            timeout: self.timeout,
+        }
+    }
+}
+
+fn parse_seconds(seconds: &str) -> Result<Duration, String> {
+    let seconds: f64 = seconds.parse().map_err(|err| format!("{err}"))?;
+    Duration::try_from_secs_f64(seconds).map_err(|err| format!("{err}"))
+}
+
+/// Images of the screen saved while the program runs
+#[derive(Debug, Args)]
+pub struct ScreenshotArgs {
+    /// Save the screen once the program stops, as PNG (`.png`), text (`.txt`) or else PBM
+    #[arg(long, value_name = "FILE")]
+    pub screenshot_at_exit: Option<PathBuf>,
+    /// Also save the screen every N instructions, adding the step count to the file name
+    #[arg(
+        long,
+        value_name = "N",
+        requires = "screenshot_at_exit",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    pub screenshot_every: Option<usize>,
+}
+
+/// Size and colors of the window
+#[cfg(feature = "sdl")]
+#[derive(Debug, Args)]
+pub struct WindowArgs {
+    /// Draw each Hack pixel as an N by N square
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u32).range(1..=16)
+    )]
+    pub scale: u32,
+    /// Cover the whole display, scaling the screen by the largest whole factor that fits
+    #[arg(long)]
+    pub fullscreen: bool,
+    /// Color of black pixels, `black`, `white` or hexadecimal like `#33ff66`
+    #[arg(long, value_name = "COLOR", default_value = "black")]
+    pub fg: Rgb,
+    /// Color of white pixels, `black`, `white` or hexadecimal like `#33ff66`
+    #[arg(long, value_name = "COLOR", default_value = "white")]
+    pub bg: Rgb,
+}
+
+#[cfg(feature = "sdl")]
+impl WindowArgs {
+    pub fn options(&self) -> WindowOptions {
+        WindowOptions {
+            scale: self.scale,
+            fullscreen: self.fullscreen,
+            foreground: self.fg,
+            background: self.bg,
+        }
+    }
+}
+
+/// Keyboard input replayed while the program runs
+#[derive(Debug, Args)]
+pub struct KeyArgs {
+    /// Press and release keys as written in a script, one `<step> press|release <key>` per line
+    #[arg(long, value_name = "FILE")]
+    pub keys: Option<PathBuf>,
+}
+
+/// Where generated code is written
+#[derive(Debug, Args)]
+pub struct OutputArgs {
+    /// File to write to instead of the default output of the subcommand
+    #[arg(short, long)]
+    pub out: Option<PathBuf>,
+}
+
+#[derive(Debug, Parser)]
+pub struct AsmArgs {
+    /// Hack assembly (`.asm`) or machine code (`.hack`)
+    pub file: PathBuf,
+    #[command(flatten)]
+    pub output: OutputArgs,
+    /// Write to standard output instead of a `.bf` file next to the input
+    #[arg(short, long)]
+    pub stdout: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct DisasmArgs {
+    /// Hack machine code (`.hack`)
+    pub file: PathBuf,
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct RunArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+    #[command(flatten)]
+    pub opt: OptArgs,
+    #[command(flatten)]
+    pub limits: LimitArgs,
+    #[command(flatten)]
+    pub screenshots: ScreenshotArgs,
+    #[command(flatten)]
+    pub keys: KeyArgs,
+    /// Run without a window, only scripted keys are pressed
+    #[arg(long)]
+    pub headless: bool,
+    /// Draw the screen in the terminal and read the keyboard from it instead of opening a window,
+    /// press Ctrl+C to stop
+    #[arg(long, conflicts_with = "headless")]
+    pub terminal: bool,
+    /// Close the window or leave the terminal as soon as the program halts, instead of waiting
+    /// until it is closed
+    #[arg(long, conflicts_with = "headless")]
+    pub exit_on_halt: bool,
+    /// Characters drawing the screen in the terminal, `braille` or `half-blocks`
+    #[arg(long, default_value = "braille", requires = "terminal")]
+    pub glyphs: Glyphs,
+    /// Write every change of the keyboard to a key script, which `--keys` replays, also in
+    /// headless runs
+    #[arg(long, value_name = "FILE", conflicts_with = "headless")]
+    pub record: Option<PathBuf>,
+    /// Number of times per second the window is redrawn
+    #[arg(
+        long,
+        default_value_t = DEFAULT_FPS,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pub fps: u32,
+    #[cfg(feature = "sdl")]
+    #[command(flatten)]
+    pub window: WindowArgs,
+    /// How the program is executed
+    #[arg(long, value_enum, default_value_t = Backend::Interpreter)]
+    pub backend: Backend,
+}
+
+#[derive(Debug, Args)]
+pub struct VmArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+    #[command(flatten)]
+    pub opt: OptArgs,
+    /// Language to print the optimized program in
+    #[arg(long, value_enum)]
+    pub emit: Emit,
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct TestArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+    #[command(flatten)]
+    pub opt: OptArgs,
+    #[command(flatten)]
+    pub limits: LimitArgs,
+    #[command(flatten)]
+    pub screenshots: ScreenshotArgs,
+    #[command(flatten)]
+    pub keys: KeyArgs,
+    /// Comma separated addresses of the words to print once the program stops
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_parser = clap::value_parser!(u16).range(0..=24576)
+    )]
+    pub ram: Vec<u16>,
+}
+
+/// Languages the optimized program can be printed in
+#[derive(Debug, Copy, Clone, ValueEnum)]
+pub enum Emit {
+    /// Instructions of the interpreter, one per line
+    Ir,
+    /// Brainfuck without comments
+    Bf,
+    /// C program that writes the screen to a PBM image when it exits
+    C,
+    /// WebAssembly text module that runs in steps and exports its tape
+    Wat,
+}
+
+/// Ways the optimized program can be executed
+#[derive(Debug, Copy, Clone, ValueEnum)]
+pub enum Backend {
+    /// Interpret the instructions one by one
+    Interpreter,
+    /// Compile the instructions into native x86-64 code
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    Jit,
+}
@@ -0,0 +1,110 @@
+// Draws the bat of a Pong game at the bottom of the screen, with the ball resting on it.
+// While the left or right arrow key is held, the bat and the ball move to the left
+// or to the right by 16 pixels at a time, until they reach the edge of the screen.
+
+        @pos
+        M=0
+        @color
+        M=-1
+
+// Draws the ball and the bat at column pos in color, 0 erases them
+(PAINT)
+        @24000
+        D=A
+        @pos
+        D=D+M
+        @addr
+        M=D
+        @2
+        D=A
+        @rows
+        M=D
+(BALL)
+        @960
+        D=A
+        @color
+        D=D&M
+        @addr
+        A=M
+        M=D
+        @32
+        D=A
+        @addr
+        M=D+M
+        @rows
+        MD=M-1
+        @BALL
+        D;JGT
+
+        @2
+        D=A
+        @rows
+        M=D
+(BAT)
+        @color
+        D=M
+        @addr
+        A=M
+        M=D
+        @32
+        D=A
+        @addr
+        M=D+M
+        @rows
+        MD=M-1
+        @BAT
+        D;JGT
+
+        // Wait for a key once drawn, or else draw them again at their new column
+        @color
+        D=M
+        @KEY
+        D;JNE
+        @step
+        D=M
+        @pos
+        M=D+M
+        @color
+        M=-1
+        @PAINT
+        0;JMP
+
+(KEY)
+        @KBD
+        D=M
+        @130
+        D=D-A
+        @LEFT
+        D;JEQ
+        @2
+        D=D-A
+        @RIGHT
+        D;JEQ
+        @KEY
+        0;JMP
+
+(LEFT)
+        @pos
+        D=M
+        @KEY
+        D;JEQ
+        @step
+        M=-1
+        @color
+        M=0
+        @PAINT
+        0;JMP
+
+(RIGHT)
+        @pos
+        D=M
+        @31
+        D=D-A
+        @KEY
+        D;JEQ
+        @step
+        M=1
+        @color
+        M=0
+        @PAINT
+        0;JMP
@@ -0,0 +1,481 @@
+use super::idiom::Idiom;
+use super::instruction::Instruction;
+use super::parser::PassManager;
+
+use anyhow::{bail, ensure, Result};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// Identifies cache files, followed by the format version
+const MAGIC: &[u8; 4] = b"BHIR";
+
+/// Bumped whenever the layout of cache files changes
+const FORMAT_VERSION: u8 = 1;
+
+/// Bumped whenever a pass rewrites programs differently, without waiting for a new crate version
+///
+/// Key scripts count optimized instructions, so the stored program must match the optimizer
+const OPTIMIZER_VERSION: u8 = 1;
+
+/// Optimized program stored next to its source, so later runs skip parsing and optimizing
+///
+/// The cache is keyed by a hash of the source, the optimizer settings, the crate version and the
+/// optimizer version, any change to them makes the stored program stale.
+#[derive(Debug, Clone)]
+pub struct IrCache {
+    path: PathBuf,
+    key: u64,
+    passes: String,
+}
+
+impl IrCache {
+    /// Creates the cache of a brainfuck file, stored with the extension `bfir`
+    ///
+    /// # Arguments
+    /// * `source_path` - Path of the brainfuck file
+    /// * `source` - Contents of the brainfuck file
+    /// * `passes` - Optimizer settings the program is parsed with
+    pub fn new(source_path: &Path, source: &[u8], passes: &PassManager) -> Self {
+        let mut hasher = Fnv::default();
+        hasher.write(env!("CARGO_PKG_VERSION").as_bytes());
+        hasher.write(&[FORMAT_VERSION, OPTIMIZER_VERSION, passes.debug() as u8]);
+        for pass in passes.passes() {
+            hasher.write(pass.name().as_bytes());
+            hasher.write(b",");
+        }
+        hasher.write(source);
+
+        let names: Vec<_> = passes.passes().iter().map(|pass| pass.name()).collect();
+        Self {
+            path: source_path.with_extension("bfir"),
+            key: hasher.0,
+            passes: names.join(","),
+        }
+    }
+
+    /// Describes the passes and the key of the cache, so key scripts timed in optimized
+    /// instructions can tell which program they were recorded with
+    pub fn settings(&self) -> String {
+        let passes = if self.passes.is_empty() {
+            "none"
+        } else {
+            &self.passes
+        };
+        format!("passes {passes}, key {:016x}", self.key)
+    }
+
+    /// Path of the cache file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reads the stored program
+    ///
+    /// # Returns
+    /// Returns None if nothing is stored or the stored program is stale,
+    /// Err if the cache file is corrupt
+    pub fn load(&self) -> Result<Option<Vec<Instruction>>> {
+        let bytes = match fs::read(&self.path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut reader = Reader(&bytes);
+        ensure!(reader.take(4)? == MAGIC, "Not a cache file");
+        if reader.u8()? != FORMAT_VERSION || reader.u64()? != self.key {
+            return Ok(None);
+        }
+
+        let len = reader.usize()?;
+        let mut instructions = Vec::with_capacity(len.min(bytes.len()));
+        for _ in 0..len {
+            instructions.push(reader.instruction()?);
+        }
+        ensure!(reader.0.is_empty(), "Trailing bytes after program");
+        validate(&instructions)?;
+
+        Ok(Some(instructions))
+    }
+
+    /// Writes a program to the cache file, replacing what was stored
+    pub fn store(&self, instructions: &[Instruction]) -> Result<()> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(FORMAT_VERSION);
+        bytes.extend(self.key.to_le_bytes());
+        usize(&mut bytes, instructions.len());
+        for instruction in instructions {
+            write_instruction(&mut bytes, instruction);
+        }
+
+        fs::write(&self.path, bytes)?;
+        Ok(())
+    }
+}
+
+/// 64-bit FNV-1a hash, unlike `DefaultHasher` it is the same across Rust releases
+struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+/// Appends an unsigned number in LEB128, 7 bits per byte with the highest bit set on all but the last
+fn usize(bytes: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+/// Appends a signed number zigzag encoded, so small negative numbers stay short
+fn isize(bytes: &mut Vec<u8>, value: isize) {
+    usize(
+        bytes,
+        ((value << 1) ^ (value >> (isize::BITS - 1))) as usize,
+    );
+}
+
+/// Appends an instruction as a tag followed by its fields
+fn write_instruction(bytes: &mut Vec<u8>, instruction: &Instruction) {
+    match *instruction {
+        Instruction::IncPtr(batch) => {
+            bytes.push(0);
+            usize(bytes, batch);
+        }
+        Instruction::DecPtr(batch) => {
+            bytes.push(1);
+            usize(bytes, batch);
+        }
+        Instruction::IncCell(batch, offset) => {
+            bytes.push(2);
+            usize(bytes, batch);
+            isize(bytes, offset);
+        }
+        Instruction::DecCell(batch, offset) => {
+            bytes.push(3);
+            usize(bytes, batch);
+            isize(bytes, offset);
+        }
+        Instruction::StartLoop(index, offset) => {
+            bytes.push(4);
+            usize(bytes, index);
+            isize(bytes, offset);
+        }
+        Instruction::EndLoop(index, offset) => {
+            bytes.push(5);
+            usize(bytes, index);
+            isize(bytes, offset);
+        }
+        Instruction::Scan(stride) => {
+            bytes.push(6);
+            isize(bytes, stride);
+        }
+        Instruction::Idiom(idiom, len) => {
+            bytes.push(7);
+            write_idiom(bytes, &idiom);
+            usize(bytes, len);
+        }
+        Instruction::Guard(len, min_offset, max_offset) => {
+            bytes.push(8);
+            usize(bytes, len);
+            isize(bytes, min_offset);
+            isize(bytes, max_offset);
+        }
+        Instruction::BreakPoint => bytes.push(9),
+    }
+}
+
+/// Appends an idiom as a tag followed by its cell offsets
+fn write_idiom(bytes: &mut Vec<u8>, idiom: &Idiom) {
+    let (tag, offsets): (u8, Vec<i16>) = match *idiom {
+        Idiom::IncWord { word, temps } => (0, vec![word.0, word.1, temps.0, temps.1]),
+        Idiom::DecWord { word, temps } => (1, vec![word.0, word.1, temps.0, temps.1]),
+        Idiom::CopyWord { src, dest, temp } => (2, vec![src.0, src.1, dest.0, dest.1, temp]),
+        Idiom::IsNonzero { word, dest, temps } => (3, vec![word.0, word.1, dest, temps.0, temps.1]),
+        Idiom::ReadMemory { base } => (4, vec![base]),
+    };
+
+    bytes.push(tag);
+    for offset in offsets {
+        isize(bytes, offset as isize);
+    }
+}
+
+/// Cursor over the unread bytes of a cache file
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        ensure!(self.0.len() >= len, "Cache file ends early");
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn i16(&mut self) -> Result<i16> {
+        Ok(self.isize()?.try_into()?)
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn usize(&mut self) -> Result<usize> {
+        let mut value = 0;
+        for shift in (0..usize::BITS).step_by(7) {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        bail!("Number too large")
+    }
+
+    fn isize(&mut self) -> Result<isize> {
+        let value = self.usize()?;
+        Ok((value >> 1) as isize ^ -((value & 1) as isize))
+    }
+
+    fn instruction(&mut self) -> Result<Instruction> {
+        Ok(match self.u8()? {
+            0 => Instruction::IncPtr(self.usize()?),
+            1 => Instruction::DecPtr(self.usize()?),
+            2 => Instruction::IncCell(self.usize()?, self.isize()?),
+            3 => Instruction::DecCell(self.usize()?, self.isize()?),
+            4 => Instruction::StartLoop(self.usize()?, self.isize()?),
+            5 => Instruction::EndLoop(self.usize()?, self.isize()?),
+            6 => Instruction::Scan(self.isize()?),
+            7 => Instruction::Idiom(self.idiom()?, self.usize()?),
+            8 => Instruction::Guard(self.usize()?, self.isize()?, self.isize()?),
+            9 => Instruction::BreakPoint,
+            tag => bail!("Unknown instruction tag {tag}"),
+        })
+    }
+
+    fn idiom(&mut self) -> Result<Idiom> {
+        Ok(match self.u8()? {
+            0 => Idiom::IncWord {
+                word: (self.i16()?, self.i16()?),
+                temps: (self.i16()?, self.i16()?),
+            },
+            1 => Idiom::DecWord {
+                word: (self.i16()?, self.i16()?),
+                temps: (self.i16()?, self.i16()?),
+            },
+            2 => Idiom::CopyWord {
+                src: (self.i16()?, self.i16()?),
+                dest: (self.i16()?, self.i16()?),
+                temp: self.i16()?,
+            },
+            3 => Idiom::IsNonzero {
+                word: (self.i16()?, self.i16()?),
+                dest: self.i16()?,
+                temps: (self.i16()?, self.i16()?),
+            },
+            4 => Idiom::ReadMemory { base: self.i16()? },
+            tag => bail!("Unknown idiom tag {tag}"),
+        })
+    }
+}
+
+/// Checks that loops are paired and regions stay inside the program, so it can run safely
+///
+/// Guarded regions run without bounds checks, they may only touch cells within their offsets
+fn validate(instructions: &[Instruction]) -> Result<()> {
+    for (i, instruction) in instructions.iter().enumerate() {
+        match *instruction {
+            Instruction::StartLoop(end, _) => ensure!(
+                matches!(instructions.get(end), Some(Instruction::EndLoop(start, _)) if *start == i),
+                "Unpaired loop at instruction {i}"
+            ),
+            Instruction::EndLoop(start, _) => ensure!(
+                matches!(instructions.get(start), Some(Instruction::StartLoop(end, _)) if *end == i),
+                "Unpaired loop at instruction {i}"
+            ),
+            Instruction::Idiom(_, len) => validate_region(instructions, i, len)?,
+            Instruction::Guard(len, min_offset, max_offset) => {
+                validate_region(instructions, i, len)?;
+                for instruction in &instructions[i + 1..=i + len] {
+                    let offset = match *instruction {
+                        Instruction::IncCell(_, offset)
+                        | Instruction::DecCell(_, offset)
+                        | Instruction::StartLoop(_, offset)
+                        | Instruction::EndLoop(_, offset) => offset,
+                        _ => bail!("Guarded region at instruction {i} moves the memory pointer"),
+                    };
+                    ensure!(
+                        (min_offset..=max_offset).contains(&offset),
+                        "Guarded region at instruction {i} leaves its bounds"
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the instructions after a region header stay inside the program and that the loops
+/// in the region are closed, so the region can be skipped or run on its own
+///
+/// # Arguments
+/// * `instructions` - Program containing the region, loops are checked to be paired beforehand
+/// * `header` - Index of the idiom or guard starting the region
+/// * `len` - Number of instructions in the region
+fn validate_region(instructions: &[Instruction], header: usize, len: usize) -> Result<()> {
+    ensure!(
+        len < instructions.len() - header,
+        "Region at instruction {header} ends past the program"
+    );
+    let region = header + 1..=header + len;
+    for instruction in &instructions[region.clone()] {
+        if let Instruction::StartLoop(other, _) | Instruction::EndLoop(other, _) = *instruction {
+            ensure!(
+                region.contains(&other),
+                "Loop crossing the end of the region at instruction {header}"
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hackfuck::parser::{Parser, Pass};
+    use std::{env, process};
+
+    /// Brainfuck file path in the temporary directory that no other test uses
+    fn source_path(test: &str) -> PathBuf {
+        env::temp_dir().join(format!("brainhack_{test}_{}.bf", process::id()))
+    }
+
+    #[test]
+    fn round_trip() -> Result<()> {
+        let source_path = source_path("cache_round_trip");
+        let source = b">>+[-<+>]<<[>>>]#";
+        let passes = PassManager::new(3, true);
+
+        let cache = IrCache::new(&source_path, source, &passes);
+        assert_eq!(cache.path(), source_path.with_extension("bfir"));
+        assert!(cache.load()?.is_none());
+
+        let instructions = passes.run(Parser::from_bytes(source)?);
+        cache.store(&instructions)?;
+        let loaded = cache.load()?.unwrap();
+        assert_eq!(format!("{loaded:?}"), format!("{instructions:?}"));
+
+        // Other settings or sources don't see the stored program
+        let stale = IrCache::new(&source_path, source, &PassManager::new(2, true));
+        assert!(stale.load()?.is_none());
+        let stale = IrCache::new(&source_path, b"+", &passes);
+        assert!(stale.load()?.is_none());
+
+        fs::remove_file(cache.path())?;
+        Ok(())
+    }
+
+    #[test]
+    fn settings() {
+        let source_path = source_path("cache_settings");
+        let passes = PassManager::from_passes(&[Pass::Batch, Pass::Scan], false);
+        let cache = IrCache::new(&source_path, b"+", &passes);
+        assert!(cache.settings().starts_with("passes batch,scan, key "));
+        assert_ne!(
+            cache.settings(),
+            IrCache::new(&source_path, b"-", &passes).settings()
+        );
+
+        let cache = IrCache::new(&source_path, b"+", &PassManager::new(0, false));
+        assert!(cache.settings().starts_with("passes none, key "));
+    }
+
+    #[test]
+    fn numbers() -> Result<()> {
+        let mut bytes = vec![];
+        for value in [0, 1, 127, 128, usize::MAX] {
+            usize(&mut bytes, value);
+        }
+        for value in [0, -1, 63, -64, isize::MIN, isize::MAX] {
+            isize(&mut bytes, value);
+        }
+
+        let mut reader = Reader(&bytes);
+        for value in [0, 1, 127, 128, usize::MAX] {
+            assert_eq!(reader.usize()?, value);
+        }
+        for value in [0, -1, 63, -64, isize::MIN, isize::MAX] {
+            assert_eq!(reader.isize()?, value);
+        }
+        assert!(reader.0.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn corrupt() -> Result<()> {
+        let source_path = source_path("cache_corrupt");
+        let passes = PassManager::new(0, true);
+        let cache = IrCache::new(&source_path, b"[]", &passes);
+
+        cache.store(&passes.run(Parser::from_bytes(b"[]")?))?;
+        let mut bytes = fs::read(cache.path())?;
+        bytes.pop();
+        fs::write(cache.path(), &bytes)?;
+        assert!(cache.load().is_err());
+
+        // Loops pointing at each other are checked
+        cache.store(&[Instruction::StartLoop(1, 0), Instruction::EndLoop(1, 0)])?;
+        assert!(cache.load().is_err());
+
+        // Guarded regions only hold cell operations and closed loops within their offsets
+        let guarded = [
+            Instruction::Guard(4, 0, 1),
+            Instruction::IncCell(1, 0),
+            Instruction::StartLoop(4, 1),
+            Instruction::DecCell(1, 1),
+            Instruction::EndLoop(2, 1),
+        ];
+        cache.store(&guarded)?;
+        assert!(cache.load()?.is_some());
+        for (index, instruction) in [
+            (3, Instruction::IncPtr(1)),
+            (3, Instruction::DecCell(1, 2)),
+            (0, Instruction::Guard(2, 0, 1)),
+        ] {
+            let mut corrupt = guarded;
+            corrupt[index] = instruction;
+            cache.store(&corrupt)?;
+            assert!(cache.load().is_err());
+        }
+
+        // Skipping an idiom can't jump into a loop
+        cache.store(&[
+            Instruction::Idiom(Idiom::ReadMemory { base: 0 }, 1),
+            Instruction::StartLoop(2, 0),
+            Instruction::EndLoop(1, 0),
+        ])?;
+        assert!(cache.load().is_err());
+
+        fs::remove_file(cache.path())?;
+        Ok(())
+    }
+}
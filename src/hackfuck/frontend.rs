@@ -0,0 +1,38 @@
+use super::screen::Screen;
+use super::tape::Tape;
+
+use anyhow::Result;
+
+/// Shows the screen and reads the keyboard while a program runs
+///
+/// The interpreter polls the input about every millisecond and presents the screen at the frame
+/// rate, independently of each other
+pub trait Frontend {
+    /// Shows the screen as the program left it so far
+    fn present(&mut self, screen: Screen) -> Result<()>;
+
+    /// Handles pending input, KBD should only be written when the keyboard changed so scripted
+    /// keys stay until a key is used
+    fn poll_input(&mut self, tape: &mut Tape) -> Result<()>;
+
+    /// Whether the user asked to stop the program, checked after each poll of the input
+    fn should_quit(&self) -> bool;
+}
+
+/// Frontend without a screen or a keyboard, for tests and CI containers
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Headless;
+
+impl Frontend for Headless {
+    fn present(&mut self, _screen: Screen) -> Result<()> {
+        Ok(())
+    }
+
+    fn poll_input(&mut self, _tape: &mut Tape) -> Result<()> {
+        Ok(())
+    }
+
+    fn should_quit(&self) -> bool {
+        false
+    }
+}
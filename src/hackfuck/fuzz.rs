@@ -0,0 +1,335 @@
+use super::consts::TAPE_SIZE;
+use super::interpreter::Interpreter;
+use super::ir::emit_bf;
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+use super::jit::Jit;
+use super::parser::Parser;
+use crate::prelude::*;
+
+use oorandom::Rand32;
+
+/// Number of random programs compared
+const PROGRAMS: u64 = 500;
+
+/// Number of instructions the unoptimized program may execute before it is discarded
+const STEP_BUDGET: usize = 200_000;
+
+/// Cells left of where every program starts, so programs can move left
+const MARGIN: usize = 32;
+
+/// Final state of a program run with a step budget
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Finished { ptr: usize, tape: Vec<u8> },
+    OutOfSteps,
+    Error,
+}
+
+/// Ways a program is parsed before it runs
+#[derive(Debug, Copy, Clone)]
+enum Variant {
+    /// `Parser::parse`, every other variant is compared to it
+    Reference,
+    /// `Parser::optimized_parse`
+    Optimized,
+    /// `Parser::parse` of the optimized program lowered by `emit_bf`
+    Emitted,
+    /// `Parser::optimized_parse` compiled by `Jit`
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    Jit,
+}
+
+/// Variants compared to the reference
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+const VARIANTS: &[Variant] = &[Variant::Optimized, Variant::Emitted, Variant::Jit];
+#[cfg(not(all(target_arch = "x86_64", target_os = "linux")))]
+const VARIANTS: &[Variant] = &[Variant::Optimized, Variant::Emitted];
+
+/// Runs a program through `Interpreter::eval_steps`
+fn run(program: &[u8], variant: Variant, budget: usize) -> Outcome {
+    let parser = Parser::from_bytes(program).unwrap();
+    let instructions = match variant {
+        Variant::Reference => parser.parse(),
+        Variant::Optimized => parser.optimized_parse(false),
+        Variant::Emitted => {
+            let code = emit_bf(&parser.optimized_parse(false));
+            Parser::from_bytes(&code).unwrap().parse()
+        }
+        #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+        Variant::Jit => parser.optimized_parse(false),
+    };
+
+    let mut interpreter = Interpreter::new(instructions.clone());
+    let finished = match variant {
+        // The JIT has no step budget, 16 ticks are over a million loop iterations
+        #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+        Variant::Jit => Jit::compile(&instructions).and_then(|jit| {
+            let mut ticks = 0;
+            jit.run(&mut interpreter.tape, &mut |_| {
+                ticks += 1;
+                ticks < 16
+            })
+        }),
+        _ => interpreter.eval_steps(budget),
+    };
+    match finished {
+        Ok(true) => Outcome::Finished {
+            ptr: interpreter.tape.get_ptr(),
+            tape: interpreter
+                .tape
+                .get_slice(0, TAPE_SIZE - 1)
+                .unwrap()
+                .to_vec(),
+        },
+        Ok(false) => Outcome::OutOfSteps,
+        Err(_) => Outcome::Error,
+    }
+}
+
+/// Runs a program as every variant
+///
+/// # Returns
+/// Returns None if the unoptimized program doesn't finish, otherwise its outcome
+/// and the first variant with a different outcome, or the last variant if all agree
+fn compare(program: &[u8]) -> Option<(Outcome, Variant, Outcome)> {
+    let reference = run(program, Variant::Reference, STEP_BUDGET);
+    if !matches!(reference, Outcome::Finished { .. }) {
+        return None;
+    }
+
+    let mut outcome = None;
+    for &variant in VARIANTS {
+        // Guards and idioms that fall back to their code add a few instructions
+        let variant_outcome = run(program, variant, 2 * STEP_BUDGET);
+        let differs = variant_outcome != reference;
+        outcome = Some((variant, variant_outcome));
+        if differs {
+            break;
+        }
+    }
+
+    let (variant, outcome) = outcome.unwrap();
+    Some((reference, variant, outcome))
+}
+
+/// Determines if any variant of a program ends up in a different state than the unoptimized one
+fn diverges(program: &[u8]) -> bool {
+    matches!(compare(program), Some((reference, _, outcome)) if reference != outcome)
+}
+
+/// Code generated by brainhack macros, so idioms are recognized in random programs
+fn fragments() -> anyhow::Result<Vec<Vec<u8>>> {
+    let mut fragments = vec![];
+
+    let mut coder = Coder::new(vec![]);
+    coder.inc_word(word::D, [pos::VU, pos::VL])?.seek(0)?;
+    fragments.push(coder.into_writer());
+
+    let mut coder = Coder::new(vec![]);
+    coder.dec_word(word::D, [pos::VU, pos::VL])?.seek(0)?;
+    fragments.push(coder.into_writer());
+
+    let mut coder = Coder::new(vec![]);
+    coder.copy_word(word::D, &[word::Q], pos::VU)?.seek(0)?;
+    fragments.push(coder.into_writer());
+
+    let mut coder = Coder::new(vec![]);
+    coder
+        .is_nonzero(word::D, pos::FU, [pos::VU, pos::VL])?
+        .seek(0)?;
+    fragments.push(coder.into_writer());
+
+    let mut coder = Coder::new(vec![]);
+    coder.read_memory()?.seek(0)?;
+    fragments.push(coder.into_writer());
+
+    Ok(fragments)
+}
+
+/// Generates a random program with balanced loops
+///
+/// # Arguments
+/// * `rng` - Source of randomness
+/// * `fragments` - Macro code that may be inserted as is, it has to return the pointer
+/// * `len` - Number of instructions, loops and fragments to generate
+/// * `depth` - Number of loops the program is nested in
+///
+/// # Returns
+/// Returns the program and how far it moves the pointer, ignoring loops that move the pointer
+fn generate(rng: &mut Rand32, fragments: &[Vec<u8>], len: usize, depth: u32) -> (Vec<u8>, isize) {
+    let mut program = vec![];
+    let mut movement = 0;
+
+    for _ in 0..len {
+        match rng.rand_range(0..20) {
+            // Cells mostly stay small, so counting loops finish quickly
+            0..=6 => program.push(b'+'),
+            7..=8 => program.push(b'-'),
+            9..=11 => {
+                program.push(b'>');
+                movement += 1;
+            }
+            12..=14 => {
+                program.push(b'<');
+                movement -= 1;
+            }
+            15..=18 if depth < 3 => {
+                let body_len = rng.rand_range(0..len as u32 / 2 + 1) as usize;
+                let (body, body_movement) = generate(rng, fragments, body_len, depth + 1);
+
+                // Most loops count their condition down and return to it, so they finish
+                if rng.rand_range(0..8) != 0 {
+                    let correction = if body_movement > 0 { b'<' } else { b'>' };
+                    program.extend(b"[->");
+                    program.extend(body);
+                    program.extend(vec![correction; body_movement.unsigned_abs()]);
+                    program.extend(b"<]");
+                } else {
+                    program.push(b'[');
+                    program.extend(body);
+                    program.push(b']');
+                }
+            }
+            19 => {
+                let fragment = rng.rand_range(0..fragments.len() as u32) as usize;
+                program.extend_from_slice(&fragments[fragment]);
+            }
+            _ => {}
+        }
+    }
+
+    (program, movement)
+}
+
+/// Determines if every loop in a program is closed
+fn balanced(program: &[u8]) -> bool {
+    let mut depth = 0;
+    for &inst in program {
+        match inst {
+            b'[' => depth += 1,
+            b']' if depth == 0 => return false,
+            b']' => depth -= 1,
+            _ => {}
+        }
+    }
+    depth == 0
+}
+
+/// Programs with a part of a program removed, largest removals first
+fn candidates(program: &[u8]) -> Vec<Vec<u8>> {
+    let mut candidates = vec![];
+
+    let mut size = program.len() / 2;
+    while size > 0 {
+        for start in (0..program.len() - size + 1).step_by(size) {
+            let candidate = [&program[..start], &program[start + size..]].concat();
+            if balanced(&candidate) {
+                candidates.push(candidate);
+            }
+        }
+        size /= 2;
+    }
+
+    // Loops are removed or replaced by their body
+    for (start, _) in program.iter().enumerate().filter(|(_, &inst)| inst == b'[') {
+        let mut depth = 0;
+        for (end, &inst) in program.iter().enumerate().skip(start) {
+            match inst {
+                b'[' => depth += 1,
+                b']' => depth -= 1,
+                _ => continue,
+            }
+            if depth == 0 {
+                candidates.push([&program[..start], &program[end + 1..]].concat());
+                candidates.push(
+                    [
+                        &program[..start],
+                        &program[start + 1..end],
+                        &program[end + 1..],
+                    ]
+                    .concat(),
+                );
+                break;
+            }
+        }
+    }
+
+    candidates
+}
+
+/// Removes parts of a diverging program for as long as it keeps diverging
+fn shrink(mut program: Vec<u8>) -> Vec<u8> {
+    while let Some(smaller) = candidates(&program)
+        .into_iter()
+        .find(|candidate| diverges(candidate))
+    {
+        program = smaller;
+    }
+    program
+}
+
+/// Summarizes how the outcomes of a program differ
+fn describe(reference: &Outcome, variant: Variant, optimized: &Outcome) -> String {
+    match (reference, optimized) {
+        (
+            Outcome::Finished { ptr, tape },
+            Outcome::Finished {
+                ptr: optimized_ptr,
+                tape: optimized_tape,
+            },
+        ) => {
+            let cell = tape.iter().zip(optimized_tape).position(|(l, r)| l != r);
+            match cell {
+                Some(cell) => format!(
+                    "{variant:?} cell {cell} is {} instead of {}",
+                    optimized_tape[cell], tape[cell]
+                ),
+                None => format!("{variant:?} pointer is {optimized_ptr} instead of {ptr}"),
+            }
+        }
+        (_, optimized) => format!("{variant:?} program ended with {optimized:?}"),
+    }
+}
+
+#[test]
+fn optimizer_matches_reference() -> anyhow::Result<()> {
+    let fragments = fragments()?;
+    let mut finished = 0;
+
+    for seed in 0..PROGRAMS {
+        let mut rng = Rand32::new(seed);
+        let len = rng.rand_range(1..40) as usize;
+        let program = [
+            &b">".repeat(MARGIN)[..],
+            &generate(&mut rng, &fragments, len, 0).0,
+        ]
+        .concat();
+
+        let Some((reference, _, outcome)) = compare(&program) else {
+            continue;
+        };
+        finished += 1;
+
+        if reference != outcome {
+            let program = shrink(program);
+            let (reference, variant, outcome) = compare(&program).unwrap();
+            panic!(
+                "Seed {seed} diverges, {}, shrunk to:\n{}",
+                describe(&reference, variant, &outcome),
+                String::from_utf8_lossy(&program)
+            );
+        }
+    }
+
+    // Most random programs should finish, otherwise nothing was compared
+    assert!(finished > PROGRAMS / 3, "only {finished} programs finished");
+    Ok(())
+}
+
+#[test]
+fn shrink_candidates() {
+    let candidates = candidates(b"+[->+<]");
+    assert!(candidates.iter().all(|candidate| balanced(candidate)));
+    assert!(candidates.contains(&b"+->+<".to_vec()));
+    assert!(candidates.contains(&b"+".to_vec()));
+}
@@ -0,0 +1,143 @@
+use super::interpreter::{Exit, Interpreter, Limits};
+use super::parser::{Parser, PassManager};
+use super::screen::{HEIGHT, WIDTH};
+use super::script::KeyScript;
+use crate::assembler::assemble_source;
+
+use std::{env, fs, path::Path};
+
+/// Directory of the example programs, their reference screens are in `screens`
+const EXAMPLES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/examples");
+
+/// Set to overwrite the reference screens with the screens the programs draw
+const BLESS: &str = "BRAINHACK_BLESS";
+
+/// Length of the header of the PBM images written by `Screen::to_pbm`
+const PBM_HEADER: usize = 11;
+
+/// Assembles an example program and runs it headless
+///
+/// # Arguments
+/// * `example` - Name of the program in the examples directory
+/// * `max_steps` - Maximum number of instructions to execute
+/// * `keys` - Key script played while the program runs
+///
+/// # Returns
+/// Returns why the program stopped and its screen as a PBM image
+fn run(example: &str, max_steps: Option<usize>, keys: &str) -> (Exit, Vec<u8>) {
+    let path = Path::new(EXAMPLES).join(format!("{example}.asm"));
+    let code = assemble_source(&fs::read_to_string(path).unwrap()).unwrap();
+    let program = PassManager::new(3, false).run(Parser::from_bytes(&code).unwrap());
+
+    let mut interpreter = Interpreter::new(program);
+    interpreter.set_keys(&KeyScript::parse(keys).unwrap());
+
+    let limits = Limits {
+        max_steps,
+        timeout: None,
+    };
+    let exit = interpreter.eval_limited(limits).unwrap();
+    (exit, interpreter.tape.screen().to_pbm())
+}
+
+/// Returns whether a pixel of a PBM image is black
+fn pixel(image: &[u8], x: usize, y: usize) -> bool {
+    image[PBM_HEADER + (y * WIDTH + x) / 8] & (0x80 >> (x % 8)) != 0
+}
+
+/// Draws the differences between two screens as a PNG image
+///
+/// Pixels black in both screens stay black, red pixels are missing and blue ones are unexpected
+fn diff_image(expected: &[u8], actual: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(WIDTH * HEIGHT * 3);
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            data.extend(match (pixel(expected, x, y), pixel(actual, x, y)) {
+                (true, true) => [0, 0, 0],
+                (false, false) => [255, 255, 255],
+                (true, false) => [255, 0, 0],
+                (false, true) => [0, 0, 255],
+            });
+        }
+    }
+
+    let mut image = vec![];
+    let mut encoder = png::Encoder::new(&mut image, WIDTH as u32, HEIGHT as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(&data).unwrap();
+    writer.finish().unwrap();
+    image
+}
+
+/// Compares a screen to its reference in the examples directory
+///
+/// On mismatch, the screen and an image of the differences are written to a temporary directory
+fn assert_screen(name: &str, actual: &[u8]) {
+    let reference = Path::new(EXAMPLES)
+        .join("screens")
+        .join(format!("{name}.pbm"));
+    if env::var_os(BLESS).is_some() {
+        fs::write(&reference, actual).unwrap();
+        return;
+    }
+
+    let expected = fs::read(&reference).unwrap();
+    if expected == actual {
+        return;
+    }
+
+    let dir = env::temp_dir().join("brainhack_golden");
+    fs::create_dir_all(&dir).unwrap();
+    let actual_path = dir.join(format!("{name}.pbm"));
+    let diff_path = dir.join(format!("{name}.diff.png"));
+    fs::write(&actual_path, actual).unwrap();
+    fs::write(&diff_path, diff_image(&expected, actual)).unwrap();
+
+    let differences = (0..HEIGHT)
+        .flat_map(|y| (0..WIDTH).map(move |x| (x, y)))
+        .filter(|&(x, y)| pixel(&expected, x, y) != pixel(actual, x, y))
+        .count();
+    panic!(
+        "{differences} pixels differ from {}, see {} and {} (set {BLESS} to update the reference)",
+        reference.display(),
+        actual_path.display(),
+        diff_path.display()
+    );
+}
+
+#[test]
+fn rect() {
+    let (exit, screen) = run("rect", None, "");
+    assert_eq!(exit, Exit::Halted);
+    assert_screen("rect", &screen);
+}
+
+#[test]
+fn fill() {
+    // Holding a key fills the screen from its last word backwards, the program only reads the
+    // keyboard between passes over the screen so the key is held from the start
+    let (exit, screen) = run("fill", Some(400_000_000), "0 press A");
+    assert_eq!(exit, Exit::MaxSteps);
+    assert_screen("fill", &screen);
+}
+
+#[test]
+fn pong() {
+    // Pong sets up the Jack OS for more than 40 billion instructions before it draws anything, so
+    // the session only checks that it runs with the scripted key without touching the screen,
+    // `bat` shows the bat and the ball moving instead
+    let keys = "200000000 press right\n300000000 release right";
+    let (exit, screen) = run("pong", Some(400_000_000), keys);
+    assert_eq!(exit, Exit::MaxSteps);
+    assert_screen("pong", &screen);
+}
+
+#[test]
+fn bat() {
+    // Holds the right arrow until the bat and the ball were moved by one word
+    let keys = "0 press right\n300000000 release right";
+    let (exit, screen) = run("bat", Some(500_000_000), keys);
+    assert_eq!(exit, Exit::MaxSteps);
+    assert_screen("bat", &screen);
+}
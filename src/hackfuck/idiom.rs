@@ -0,0 +1,230 @@
+use super::instruction::Instruction;
+use super::parser::Parser;
+use crate::prelude::*;
+
+use anyhow::Result;
+use std::collections::HashMap;
+
+/// High-level operations recognized in code generated by the brainhack assembler
+///
+/// Cells are given as offsets from the memory pointer. Each idiom is placed in front of
+/// the instructions it was recognized from, which still run whenever its preconditions
+/// don't hold.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Idiom {
+    /// 16-bit increment generated by `Arith::inc_word`
+    IncWord { word: (i16, i16), temps: (i16, i16) },
+    /// 16-bit decrement generated by `Arith::dec_word`
+    DecWord { word: (i16, i16), temps: (i16, i16) },
+    /// Word copy generated by `Arith::copy_word` with a single destination
+    CopyWord {
+        src: (i16, i16),
+        dest: (i16, i16),
+        temp: i16,
+    },
+    /// Zero test generated by `Arith::is_nonzero`
+    IsNonzero {
+        word: (i16, i16),
+        dest: i16,
+        temps: (i16, i16),
+    },
+    /// RAM load by address generated by `Memory::read_memory`, `base` is the offset of `pos::AU`
+    ReadMemory { base: i16 },
+}
+
+impl Idiom {
+    /// Generates the brainfuck code the idiom is recognized from
+    fn code(&self) -> Result<Vec<u8>> {
+        let mut coder = Coder::new(vec![]);
+
+        match *self {
+            Idiom::IncWord { word, temps } => {
+                coder.inc_word(to_word(word), [temps.0.into(), temps.1.into()])?;
+            }
+            Idiom::DecWord { word, temps } => {
+                coder.dec_word(to_word(word), [temps.0.into(), temps.1.into()])?;
+            }
+            Idiom::CopyWord { src, dest, temp } => {
+                coder.copy_word(to_word(src), &[to_word(dest)], temp.into())?;
+            }
+            Idiom::IsNonzero { word, dest, temps } => {
+                coder.is_nonzero(
+                    to_word(word),
+                    dest.into(),
+                    [temps.0.into(), temps.1.into()],
+                )?;
+            }
+            Idiom::ReadMemory { base } => {
+                coder.set_pos(pos::AU - Pos::from(base))?.read_memory()?;
+            }
+        }
+
+        Ok(coder.into_writer())
+    }
+
+    /// Guesses the idioms a sequence of instructions could start with, longest shapes first
+    fn candidates(window: &[Instruction]) -> [Option<Idiom>; 5] {
+        [
+            Self::read_memory_candidate(window),
+            Self::is_nonzero_candidate(window),
+            Self::inc_word_candidate(window),
+            Self::dec_word_candidate(window),
+            Self::copy_word_candidate(window),
+        ]
+    }
+
+    fn read_memory_candidate(window: &[Instruction]) -> Option<Idiom> {
+        // Starts by copying A out of the register buffer
+        match window.first()? {
+            Instruction::StartLoop(_, offset) => Some(Idiom::ReadMemory {
+                base: (*offset - pos::AU).try_into().ok()?,
+            }),
+            _ => None,
+        }
+    }
+
+    fn is_nonzero_candidate(window: &[Instruction]) -> Option<Idiom> {
+        // Copies both bytes aside using dest as the temporary cell
+        match (window.first()?, window.get(2)?, window.get(3)?, window.get(9)?, window.get(11)?) {
+            (
+                Instruction::StartLoop(_, upper),
+                Instruction::IncCell(_, temp0),
+                Instruction::IncCell(_, dest),
+                Instruction::StartLoop(_, lower),
+                Instruction::IncCell(_, temp1),
+            ) => {
+                let [upper, lower, dest, temp0, temp1] =
+                    distinct([*upper, *lower, *dest, *temp0, *temp1])?;
+                Some(Idiom::IsNonzero {
+                    word: (upper, lower),
+                    dest,
+                    temps: (temp0, temp1),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn inc_word_candidate(window: &[Instruction]) -> Option<Idiom> {
+        // Increments the lower byte, then carries into the upper byte
+        match (window.first()?, window.get(3)?, window.get(4)?, window.get(21)?) {
+            (
+                Instruction::IncCell(_, lower),
+                Instruction::IncCell(_, temp0),
+                Instruction::IncCell(_, temp1),
+                Instruction::IncCell(_, upper),
+            ) => {
+                let [upper, lower, temp0, temp1] = distinct([*upper, *lower, *temp0, *temp1])?;
+                Some(Idiom::IncWord {
+                    word: (upper, lower),
+                    temps: (temp0, temp1),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn dec_word_candidate(window: &[Instruction]) -> Option<Idiom> {
+        // Borrows from the upper byte, then decrements the lower byte
+        match (window.first()?, window.get(2)?, window.get(3)?, window.get(20)?) {
+            (
+                Instruction::StartLoop(_, lower),
+                Instruction::IncCell(_, temp0),
+                Instruction::IncCell(_, temp1),
+                Instruction::DecCell(_, upper),
+            ) => {
+                let [upper, lower, temp0, temp1] = distinct([*upper, *lower, *temp0, *temp1])?;
+                Some(Idiom::DecWord {
+                    word: (upper, lower),
+                    temps: (temp0, temp1),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn copy_word_candidate(window: &[Instruction]) -> Option<Idiom> {
+        // Copies the upper byte, then the lower byte through the same temporary cell
+        match (window.first()?, window.get(2)?, window.get(3)?, window.get(9)?, window.get(11)?) {
+            (
+                Instruction::StartLoop(_, src_upper),
+                Instruction::IncCell(_, dest_upper),
+                Instruction::IncCell(_, temp),
+                Instruction::StartLoop(_, src_lower),
+                Instruction::IncCell(_, dest_lower),
+            ) => {
+                let [src_upper, src_lower, dest_upper, dest_lower, temp] =
+                    distinct([*src_upper, *src_lower, *dest_upper, *dest_lower, *temp])?;
+                Some(Idiom::CopyWord {
+                    src: (src_upper, src_lower),
+                    dest: (dest_upper, dest_lower),
+                    temp,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Optimized instructions every idiom is generated as, built on first use
+#[derive(Default)]
+pub(super) struct Templates {
+    cache: HashMap<Idiom, Vec<Instruction>>,
+}
+
+impl Templates {
+    /// Finds the idiom a sequence of instructions starts with
+    ///
+    /// # Arguments
+    /// * `window` - Instructions following the current position
+    ///
+    /// # Returns
+    /// Returns the idiom and the number of instructions it replaces
+    pub(super) fn recognize(&mut self, window: &[Instruction]) -> Option<(Idiom, usize)> {
+        Idiom::candidates(window)
+            .into_iter()
+            .flatten()
+            .find_map(|idiom| {
+                let template = self.template(idiom);
+                let matches = !template.is_empty()
+                    && window.len() >= template.len()
+                    && template
+                        .iter()
+                        .zip(window)
+                        .all(|(expected, instruction)| expected.same_shape(instruction));
+
+                matches.then_some((idiom, template.len()))
+            })
+    }
+
+    fn template(&mut self, idiom: Idiom) -> &[Instruction] {
+        self.cache.entry(idiom).or_insert_with(|| {
+            // Shapes that can't be generated never match
+            idiom
+                .code()
+                .and_then(|code| Parser::from_bytes(&code))
+                .map(Parser::template_parse)
+                .unwrap_or_default()
+        })
+    }
+}
+
+/// Converts cell offsets to a `Word`
+fn to_word(word: (i16, i16)) -> Word {
+    (word.0.into(), word.1.into())
+}
+
+/// Narrows cell offsets, returns None if they don't fit or refer to the same cell
+fn distinct<const N: usize>(offsets: [isize; N]) -> Option<[i16; N]> {
+    for (i, offset) in offsets.iter().enumerate() {
+        if offsets[..i].contains(offset) {
+            return None;
+        }
+    }
+
+    let mut narrowed = [0; N];
+    for (cell, offset) in narrowed.iter_mut().zip(offsets) {
+        *cell = offset.try_into().ok()?;
+    }
+    Some(narrowed)
+}
@@ -1,14 +1,19 @@
+use super::idiom::Idiom;
+
 use anyhow::{bail, Result};
 
 /// Data structure to represent abstract brainfuck operations
 #[derive(Debug, Copy, Clone)]
 pub enum Instruction {
-    IncPtr(usize),         // (Batch size)
-    DecPtr(usize),         // (Batch size)
-    IncCell(usize, isize), // (Batch size, mem_ptr offset)
-    DecCell(usize, isize), // (Batch size, mem_ptr offset)
-    StartLoop(usize),      // (Index of matching EndLoop)
-    EndLoop(usize),        // (Index of matching StartLoop)
+    IncPtr(usize),           // (Batch size)
+    DecPtr(usize),           // (Batch size)
+    IncCell(usize, isize),   // (Batch size, mem_ptr offset)
+    DecCell(usize, isize),   // (Batch size, mem_ptr offset)
+    StartLoop(usize, isize), // (Index of matching EndLoop, mem_ptr offset of condition)
+    EndLoop(usize, isize),   // (Index of matching StartLoop, mem_ptr offset of condition)
+    Scan(isize),             // (Stride, negative when scanning left)
+    Idiom(Idiom, usize),     // (Recognized operation, number of following instructions it replaces)
+    Guard(usize, isize, isize), // (Number of following instructions, lowest and highest mem_ptr offset)
     // Custom instruction for debugging
     BreakPoint,
 }
@@ -33,6 +38,25 @@ impl Instruction {
         Ok(())
     }
 
+    /// Returns Instruction batch, None if instruction can't be grouped
+    pub(super) fn batch(&self) -> Option<usize> {
+        match self {
+            Instruction::IncPtr(batch)
+            | Instruction::DecPtr(batch)
+            | Instruction::IncCell(batch, _)
+            | Instruction::DecCell(batch, _) => Some(*batch),
+            _ => None,
+        }
+    }
+
+    /// Returns Instruction offset, None if instruction doesn't operate on the cell value
+    pub(super) fn offset(&self) -> Option<isize> {
+        match self {
+            Instruction::IncCell(_, offset) | Instruction::DecCell(_, offset) => Some(*offset),
+            _ => None,
+        }
+    }
+
     /// Updates Instruction offset, changes which relative cell the instruction operates on
     ///
     /// # Arguments
@@ -60,8 +84,8 @@ impl Instruction {
     ///
     pub(super) fn update_loop(&mut self, index: usize) -> Result<()> {
         match self {
-            Instruction::StartLoop(i) => *i = index,
-            Instruction::EndLoop(i) => *i = index,
+            Instruction::StartLoop(i, _) => *i = index,
+            Instruction::EndLoop(i, _) => *i = index,
             _ => bail!("Cannot update loop index of this instruction"),
         }
         Ok(())
@@ -79,6 +103,24 @@ impl Instruction {
     pub(super) fn mem_op(&self) -> bool {
         matches!(self, &Instruction::IncPtr(_) | &Instruction::DecPtr(_))
     }
+
+    /// Determine if instructions are identical, including batches and offsets
+    ///
+    /// Loop indices are not compared
+    pub(super) fn same_shape(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::IncPtr(l), Self::IncPtr(r)) | (Self::DecPtr(l), Self::DecPtr(r)) => l == r,
+            (Self::IncCell(l0, l1), Self::IncCell(r0, r1))
+            | (Self::DecCell(l0, l1), Self::DecCell(r0, r1)) => l0 == r0 && l1 == r1,
+            (Self::StartLoop(_, l), Self::StartLoop(_, r))
+            | (Self::EndLoop(_, l), Self::EndLoop(_, r))
+            | (Self::Scan(l), Self::Scan(r)) => l == r,
+            (Self::Idiom(l0, l1), Self::Idiom(r0, r1)) => l0 == r0 && l1 == r1,
+            (Self::Guard(l0, l1, l2), Self::Guard(r0, r1, r2)) => l0 == r0 && l1 == r1 && l2 == r2,
+            (Self::BreakPoint, Self::BreakPoint) => true,
+            _ => false,
+        }
+    }
 }
 
 impl PartialEq for Instruction {
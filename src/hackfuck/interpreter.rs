@@ -1,27 +1,259 @@
+use super::frontend::{Frontend, Headless};
 use super::instruction::Instruction;
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+use super::jit::Jit;
+use super::script::{KeyScript, Player, Recorder};
 use super::tape::Tape;
 
-use anyhow::Result;
-use sdl2::event::Event;
-use sdl2::pixels::Color;
-use sdl2::render::Canvas;
-use sdl2::video::Window;
-use sdl2::Sdl;
-use std::marker::PhantomData;
+use anyhow::{bail, Result};
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
 
-pub struct IO;
-pub struct PURE;
+/// Number of instructions executed between checks of the time limit and the next frame
+const CLOCK_INTERVAL: usize = 1 << 16;
+
+/// Number of times per second the screen is redrawn unless set otherwise
+pub const DEFAULT_FPS: u32 = 60;
+
+/// Time between two checks for key presses and the window being closed
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Limits on how long a program runs, unlimited by default
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Limits {
+    /// Maximum number of instructions to execute
+    pub max_steps: Option<usize>,
+    /// Maximum time to run for
+    pub timeout: Option<Duration>,
+}
+
+/// Reason a program stopped running
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    /// Every instruction was executed, assembled programs stop like this once they halt
+    Halted,
+    /// The program executed the maximum number of instructions
+    MaxSteps,
+    /// The program ran out of time
+    Timeout,
+    /// The frontend asked to stop, for example because the window was closed
+    Closed,
+}
+
+impl fmt::Display for Exit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Exit::Halted => "Program halted",
+            Exit::MaxSteps => "Program stopped: step limit reached",
+            Exit::Timeout => "Program stopped: time limit reached",
+            Exit::Closed => "Program stopped: closed by the user",
+        })
+    }
+}
+
+/// Function called with the tape and the number of executed instructions
+type Callback<'a> = Box<dyn FnMut(&mut Tape, usize) -> Result<()> + 'a>;
+
+/// Gets called with the tape each time the program executed a number of instructions
+pub struct Observer<'a> {
+    /// Number of instructions between calls
+    every: usize,
+    /// Number of executed instructions at the next call
+    next: usize,
+    callback: Callback<'a>,
+}
+
+impl<'a> Observer<'a> {
+    /// Creates an observer, guarded regions are never interrupted so calls can come a bit late
+    ///
+    /// # Arguments
+    /// * `every` - Number of instructions between calls, at least one
+    /// * `callback` - Called with the tape, which it may change, and the number of executed instructions
+    pub fn new(every: usize, callback: impl FnMut(&mut Tape, usize) -> Result<()> + 'a) -> Self {
+        let every = every.max(1);
+        Self {
+            every,
+            next: every,
+            callback: Box::new(callback),
+        }
+    }
+
+    /// Calls the callback if enough instructions were executed since the last call
+    fn poll(&mut self, tape: &mut Tape, steps: usize) -> Result<()> {
+        if steps >= self.next {
+            (self.callback)(tape, steps)?;
+            self.next = (steps / self.every + 1) * self.every;
+        }
+        Ok(())
+    }
+}
+
+/// Counts executed instructions against the limits
+struct Clock {
+    /// Number of executed instructions
+    steps: usize,
+    /// Remaining number of instructions to execute
+    budget: usize,
+    deadline: Option<Instant>,
+    /// Number of instructions until the deadline is checked
+    until_check: usize,
+}
+
+impl Clock {
+    fn new(limits: Limits) -> Self {
+        Self {
+            steps: 0,
+            budget: limits.max_steps.unwrap_or(usize::MAX),
+            deadline: limits
+                .timeout
+                .and_then(|timeout| Instant::now().checked_add(timeout)),
+            until_check: CLOCK_INTERVAL,
+        }
+    }
+
+    /// Counts an instruction about to be executed
+    ///
+    /// # Returns
+    /// Returns the reason to stop instead if a limit was reached
+    fn tick(&mut self) -> Option<Exit> {
+        if self.budget == 0 {
+            return Some(Exit::MaxSteps);
+        }
+        self.budget -= 1;
+        self.steps += 1;
+
+        self.until_check -= 1;
+        if self.until_check == 0 {
+            self.until_check = CLOCK_INTERVAL;
+            if self.timed_out() {
+                return Some(Exit::Timeout);
+            }
+        }
+        None
+    }
+
+    fn timed_out(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+/// Decides when events are handled and the screen is redrawn, independently of each other
+struct Schedule {
+    frame_time: Duration,
+    next_poll: Instant,
+    next_frame: Instant,
+    /// Number of frames drawn
+    frames: usize,
+}
+
+impl Schedule {
+    fn new(frame_time: Duration) -> Self {
+        let now = Instant::now();
+        Self {
+            frame_time,
+            next_poll: now,
+            next_frame: now + frame_time,
+            frames: 0,
+        }
+    }
+
+    /// Returns whether it's time to handle events and whether it's time to redraw the screen
+    fn due(&mut self) -> (bool, bool) {
+        let now = Instant::now();
+        let poll = now >= self.next_poll;
+        if poll {
+            self.next_poll = now + POLL_INTERVAL;
+        }
+        let frame = now >= self.next_frame;
+        if frame {
+            self.next_frame = now + self.frame_time;
+            self.frames += 1;
+        }
+        (poll, frame)
+    }
+
+    /// Handles pending input and redraws the screen once it's time to
+    ///
+    /// # Arguments
+    /// * `frontend` - Shows the screen and reads the keyboard
+    /// * `tape` - Tape the program runs on
+    /// * `keys` - Scripted keys, those timed in frames are applied after each redraw
+    ///
+    /// # Returns
+    /// Returns false if the frontend asked to stop
+    fn update(
+        &mut self,
+        frontend: &mut dyn Frontend,
+        tape: &mut Tape,
+        keys: &mut Player,
+    ) -> Result<bool> {
+        let (poll, frame) = self.due();
+        if poll {
+            frontend.poll_input(tape)?;
+            if frontend.should_quit() {
+                return Ok(false);
+            }
+        }
+        if frame {
+            frontend.present(tape.screen())?;
+            keys.play_frames(self.frames, tape);
+        }
+        Ok(true)
+    }
+}
 
 /// Program that can run brainfuck code and manage the memory tape
-pub struct Interpreter<Type> {
+pub struct Interpreter {
     pub tape: Tape,
     instructions: Vec<Instruction>,
-    sdl_context: Option<Sdl>,
-    canvas: Option<Canvas<Window>>,
-    type_: PhantomData<Type>,
+    /// Time between two redraws of the screen
+    frame_time: Duration,
+    /// Keys pressed and released by a script
+    keys: Player,
+    /// Changes of KBD recorded while the program runs
+    recorder: Option<Recorder>,
 }
 
-impl<Type> Interpreter<Type> {
+impl Interpreter {
+    /// Create a new interpreter, it runs without IO unless given a frontend
+    pub fn new(instructions: Vec<Instruction>) -> Interpreter {
+        Interpreter {
+            tape: Tape::new(),
+            instructions,
+            frame_time: Duration::from_secs(1) / DEFAULT_FPS,
+            keys: Player::default(),
+            recorder: None,
+        }
+    }
+
+    /// Sets how many times per second the screen is redrawn
+    ///
+    /// # Arguments
+    /// * `fps` - Number of frames per second, at least one
+    pub fn set_fps(&mut self, fps: u32) {
+        self.frame_time = Duration::from_secs(1) / fps.max(1);
+    }
+
+    /// Records every change of KBD while the program runs, replacing any previous recording
+    pub fn record_keys(&mut self) {
+        self.recorder = Some(Recorder::default());
+    }
+
+    /// Returns the changes of KBD recorded so far, as a key script timed in steps
+    pub fn recorded_keys(&self) -> Option<&KeyScript> {
+        self.recorder.as_ref().map(Recorder::script)
+    }
+
+    /// Presses and releases keys as the program runs, replacing any previous script
+    ///
+    /// # Arguments
+    /// * `script` - Keys to apply, at a step count they change before the next instruction
+    pub fn set_keys(&mut self, script: &KeyScript) {
+        self.keys = Player::new(script);
+    }
+
     /// Replace program with new instructions
     #[allow(dead_code)]
     pub fn load(&mut self, instructions: Vec<Instruction>) {
@@ -31,137 +263,318 @@ impl<Type> Interpreter<Type> {
     /// Run the program without any IO
     #[allow(dead_code)]
     pub fn eval(&mut self) -> Result<()> {
-        let mut i = 0;
-        while i < self.instructions.len() {
-            match self.instructions[i] {
-                Instruction::IncPtr(batch) => self.tape.inc_ptr(batch)?,
-                Instruction::DecPtr(batch) => self.tape.dec_ptr(batch)?,
-                Instruction::IncCell(batch, offset) => self.tape.inc_cell(batch, offset)?,
-                Instruction::DecCell(batch, offset) => self.tape.dec_cell(batch, offset)?,
-                Instruction::StartLoop(index) => {
-                    if self.tape.get_cell() == 0 {
-                        i = index
-                    }
-                }
-                Instruction::EndLoop(index) => {
-                    if self.tape.get_cell() != 0 {
-                        i = index
-                    }
-                }
-                Instruction::BreakPoint => self.tape.breakpoint(),
-            }
-            i += 1;
-        }
+        self.eval_limited(Limits::default())?;
         Ok(())
     }
-}
 
-/// Operations only available with IO disabled
-impl Interpreter<PURE> {
-    /// Create a new interpreter with no IO
-    pub fn new(instructions: Vec<Instruction>) -> Interpreter<PURE> {
-        Interpreter::<PURE> {
-            tape: Tape::new(),
-            instructions,
-            sdl_context: None,
-            canvas: None,
-            type_: PhantomData::<PURE>,
+    /// Run the program without any IO, compiled into native code
+    ///
+    /// # Arguments
+    /// * `limits` - Only the time limit is supported, it is checked between loop iterations
+    ///
+    /// # Returns
+    /// Returns why the program stopped
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    pub fn eval_jit(&mut self, limits: Limits) -> Result<Exit> {
+        if self.keys.uses_frames() {
+            bail!("Frame numbers in key scripts need a window");
+        }
+        self.run_jit(&mut Headless, limits)
+    }
+
+    /// Run the program without any IO, executing a limited number of instructions
+    ///
+    /// # Arguments
+    /// * `budget` - Maximum number of instructions to execute
+    ///
+    /// # Returns
+    /// Returns false if the program didn't finish within the budget
+    pub fn eval_steps(&mut self, budget: usize) -> Result<bool> {
+        let limits = Limits {
+            max_steps: Some(budget),
+            timeout: None,
+        };
+        Ok(self.eval_limited(limits)? == Exit::Halted)
+    }
+
+    /// Run the program without any IO until it finishes or reaches a limit
+    ///
+    /// # Arguments
+    /// * `limits` - Maximum number of instructions to execute and time to run for
+    ///
+    /// # Returns
+    /// Returns why the program stopped
+    pub fn eval_limited(&mut self, limits: Limits) -> Result<Exit> {
+        self.eval_observed(limits, None)
+    }
+
+    /// Run the program without any IO until it finishes or reaches a limit, watching the tape
+    ///
+    /// # Arguments
+    /// * `limits` - Maximum number of instructions to execute and time to run for
+    /// * `observer` - Called with the tape every few instructions
+    ///
+    /// # Returns
+    /// Returns why the program stopped
+    pub fn eval_observed(
+        &mut self,
+        limits: Limits,
+        observer: Option<&mut Observer>,
+    ) -> Result<Exit> {
+        if self.keys.uses_frames() {
+            bail!("Frame numbers in key scripts need a window");
         }
+        self.run(&mut Headless, limits, observer)
     }
 
-    /// Initialize IO operations
-    pub fn init_screen(self) -> Interpreter<IO> {
-        let sdl_context = sdl2::init().unwrap();
+    /// Run the program, showing the screen and reading the keyboard through a frontend
+    ///
+    /// # Arguments
+    /// * `frontend` - Shows the screen and reads the keyboard
+    /// * `limits` - Maximum number of instructions to execute and time to run for
+    /// * `observer` - Called with the tape every few instructions
+    ///
+    /// # Returns
+    /// Returns why the program stopped, `Exit::Closed` once the frontend asked to stop
+    pub fn run(
+        &mut self,
+        frontend: &mut dyn Frontend,
+        limits: Limits,
+        observer: Option<&mut Observer>,
+    ) -> Result<Exit> {
+        let mut schedule = Schedule::new(self.frame_time);
+        self.keys.play_frames(0, &mut self.tape);
 
-        let window = sdl_context
-            .video()
-            .unwrap()
-            .window("BrainHack", 512, 256)
-            .position_centered()
-            .build()
-            .unwrap();
+        let exit = execute(
+            &mut self.tape,
+            &self.instructions,
+            limits,
+            &mut self.keys,
+            self.recorder.as_mut(),
+            observer,
+            |tape, keys| schedule.update(frontend, tape, keys),
+        )?;
 
-        let canvas = window.into_canvas().build().unwrap();
+        // Shows the screen as the program left it
+        if exit != Exit::Closed {
+            frontend.present(self.tape.screen())?;
+        }
+        Ok(exit)
+    }
+
+    /// Keeps showing the screen until the frontend asks to stop, so it can be looked at once the
+    /// program halted, never returns with `Headless`
+    ///
+    /// # Arguments
+    /// * `frontend` - Shows the screen and reads the keyboard
+    pub fn wait_for_quit(&mut self, frontend: &mut dyn Frontend) -> Result<()> {
+        loop {
+            frontend.poll_input(&mut self.tape)?;
+            if frontend.should_quit() {
+                return Ok(());
+            }
+            // Windows may need to be drawn again after being resized or uncovered
+            frontend.present(self.tape.screen())?;
+            thread::sleep(self.frame_time);
+        }
+    }
+
+    /// Run the program compiled into native code, redrawing the screen at the frame rate
+    ///
+    /// # Arguments
+    /// * `frontend` - Shows the screen and reads the keyboard
+    /// * `limits` - Only the time limit is supported, it is checked between loop iterations
+    ///
+    /// # Returns
+    /// Returns why the program stopped
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    pub fn run_jit(&mut self, frontend: &mut dyn Frontend, limits: Limits) -> Result<Exit> {
+        if limits.max_steps.is_some() || self.keys.uses_steps() || self.recorder.is_some() {
+            bail!("Compiled programs can't count executed instructions");
+        }
+        let jit = Jit::compile(&self.instructions)?;
+        let clock = Clock::new(limits);
+        let mut schedule = Schedule::new(self.frame_time);
+        let keys = &mut self.keys;
+        keys.play_frames(0, &mut self.tape);
+
+        let mut exit = Ok(Exit::Halted);
+        jit.run(&mut self.tape, &mut |tape| {
+            if clock.timed_out() {
+                exit = Ok(Exit::Timeout);
+                return false;
+            }
+            match schedule.update(frontend, tape, keys) {
+                Ok(true) => true,
+                Ok(false) => {
+                    exit = Ok(Exit::Closed);
+                    false
+                }
+                Err(error) => {
+                    exit = Err(error);
+                    false
+                }
+            }
+        })?;
 
-        Interpreter::<IO> {
-            tape: self.tape,
-            instructions: self.instructions,
-            sdl_context: Some(sdl_context),
-            canvas: Some(canvas),
-            type_: PhantomData::<IO>,
+        let exit = exit?;
+        if exit != Exit::Closed {
+            frontend.present(self.tape.screen())?;
         }
+        Ok(exit)
     }
 }
 
-/// Operations only available with IO enabled
-impl Interpreter<IO> {
-    pub fn run(&mut self) -> Result<()> {
-        let mut i = 0;
-        let mut writes = 1;
-        let mut event_pump = self.sdl_context.as_mut().unwrap().event_pump().unwrap();
-        let canvas = self.canvas.as_mut().unwrap();
-        'event_loop: loop {
-            if i >= self.instructions.len() {
-                break 'event_loop;
+/// Runs a program until it finishes or reaches a limit
+///
+/// # Arguments
+/// * `tape` - Tape the program runs on
+/// * `instructions` - Program to run
+/// * `limits` - Maximum number of instructions to execute and time to run for
+/// * `keys` - Scripted keys, those timed in steps are applied before the next instruction
+/// * `recorder` - Records the changes of KBD made by the keys and by `update`
+/// * `observer` - Called with the tape every few instructions
+/// * `update` - Called with the tape every few thousand instructions to show the screen and read
+///   the keyboard, the program stops once it returns false
+///
+/// # Returns
+/// Returns why the program stopped
+fn execute(
+    tape: &mut Tape,
+    instructions: &[Instruction],
+    limits: Limits,
+    keys: &mut Player,
+    mut recorder: Option<&mut Recorder>,
+    mut observer: Option<&mut Observer>,
+    mut update: impl FnMut(&mut Tape, &mut Player) -> Result<bool>,
+) -> Result<Exit> {
+    let mut i = 0;
+    let mut until_update = CLOCK_INTERVAL;
+    let mut clock = Clock::new(limits);
+    if let Some(recorder) = recorder.as_deref_mut() {
+        recorder.record(0, tape);
+    }
+    let mut next_key = keys.next_step();
+    while i < instructions.len() {
+        if clock.steps >= next_key {
+            next_key = keys.play_steps(clock.steps, tape);
+            if let Some(recorder) = recorder.as_deref_mut() {
+                recorder.record(clock.steps, tape);
             }
+        }
 
-            if self.tape.io_write {
-                writes += 1;
-                self.tape.io_write = false;
+        // Reading the time after every instruction would slow the program down
+        until_update -= 1;
+        if until_update == 0 {
+            until_update = CLOCK_INTERVAL;
+            if !update(tape, keys)? {
+                return Ok(Exit::Closed);
             }
+            // Keys change before the next instruction, like scripted keys at this step count
+            if let Some(recorder) = recorder.as_deref_mut() {
+                recorder.record(clock.steps, tape);
+            }
+        }
 
-            // Arbitrary number to limit the number of times the screen is redrawn
-            // Adjust as per your needs
-            // Big programs will need a higher number
-            if writes % 100000 == 0 { 
-                canvas.set_draw_color(Color::RGB(255, 255, 255));
-                canvas.clear();
-                canvas.set_draw_color(Color::RGB(0, 0, 0));
-
-                let points = self.tape.get_pixels();
-                canvas.draw_points(points.as_slice()).unwrap();
-                canvas.present();
-                // ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60)); // sloppy FPS limit
-
-                for event in event_pump.poll_iter() {
-                    match event {
-                        Event::Quit { .. } => break 'event_loop,
-                        Event::KeyDown { keycode, .. } => self.tape.update_kbd(keycode.unwrap()),
-                        _ => {}
-                    }
+        if let Some(observer) = observer.as_deref_mut() {
+            observer.poll(tape, clock.steps)?;
+        }
+        if let Some(exit) = clock.tick() {
+            return Ok(exit);
+        }
+
+        match instructions[i] {
+            Instruction::IncPtr(batch) => tape.inc_ptr(batch)?,
+            Instruction::DecPtr(batch) => tape.dec_ptr(batch)?,
+            Instruction::IncCell(batch, offset) => tape.inc_cell(batch, offset)?,
+            Instruction::DecCell(batch, offset) => tape.dec_cell(batch, offset)?,
+            Instruction::StartLoop(index, offset) => {
+                if tape.get_cell(offset)? == 0 {
+                    i = index
                 }
-                writes = 1;
             }
-
-            // Execute the next instruction
-            match self.instructions[i] {
-                Instruction::IncPtr(batch) => self.tape.inc_ptr(batch)?,
-                Instruction::DecPtr(batch) => self.tape.dec_ptr(batch)?,
-                Instruction::IncCell(batch, offset) => self.tape.inc_cell(batch, offset)?,
-                Instruction::DecCell(batch, offset) => self.tape.dec_cell(batch, offset)?,
-                Instruction::StartLoop(index) => {
-                    if self.tape.get_cell() == 0 {
-                        i = index
-                    }
+            Instruction::EndLoop(index, offset) => {
+                if tape.get_cell(offset)? != 0 {
+                    i = index
                 }
-                Instruction::EndLoop(index) => {
-                    if self.tape.get_cell() != 0 {
-                        i = index
+            }
+            Instruction::Scan(stride) => tape.scan(stride)?,
+            Instruction::Idiom(idiom, len) => {
+                if tape.idiom(idiom)? {
+                    i += len
+                }
+            }
+            Instruction::Guard(len, min_offset, max_offset) => {
+                // Out of bounds regions run checked, so the error is raised where it happens
+                if tape.in_bounds(min_offset, max_offset) {
+                    if let Some(exit) =
+                        eval_unchecked(tape, instructions, i + 1, i + 1 + len, &mut clock)
+                    {
+                        return Ok(exit);
                     }
+                    i += len
                 }
-                Instruction::BreakPoint => self.tape.breakpoint(),
             }
-            i += 1;
+            Instruction::BreakPoint => tape.breakpoint(),
         }
+        i += 1;
+    }
+    Ok(Exit::Halted)
+}
 
-        Ok(())
+/// Runs a guarded region, the memory pointer doesn't move inside of it
+///
+/// # Arguments
+/// * `tape` - Tape the region's bounds were checked on
+/// * `instructions` - Program containing the region, loop indices are absolute
+/// * `start` - Index of the first instruction in the region
+/// * `end` - Index after the last instruction in the region
+/// * `clock` - Counts the executed instructions against the limits
+///
+/// # Returns
+/// Returns the reason to stop if a limit was reached before the region finished
+fn eval_unchecked(
+    tape: &mut Tape,
+    instructions: &[Instruction],
+    start: usize,
+    end: usize,
+    clock: &mut Clock,
+) -> Option<Exit> {
+    let mut i = start;
+    while i < end {
+        if let Some(exit) = clock.tick() {
+            return Some(exit);
+        }
+
+        match instructions[i] {
+            Instruction::IncCell(batch, offset) => tape.inc_cell_unchecked(batch, offset),
+            Instruction::DecCell(batch, offset) => tape.dec_cell_unchecked(batch, offset),
+            Instruction::StartLoop(index, offset) => {
+                if tape.get_cell_unchecked(offset) == 0 {
+                    i = index
+                }
+            }
+            Instruction::EndLoop(index, offset) => {
+                if tape.get_cell_unchecked(offset) != 0 {
+                    i = index
+                }
+            }
+            _ => unreachable!("Guarded regions only contain cell operations and balanced loops"),
+        }
+        i += 1;
     }
+    None
 }
 
 #[cfg(test)]
 mod interpreter_test {
     use super::*;
-    use crate::hackfuck::parser::Parser;
+    use crate::hackfuck::{
+        idiom::Idiom,
+        parser::{Parser, Pass, PassManager},
+        screen::Screen,
+    };
+    use crate::prelude::*;
 
     #[test]
     fn test_optimizations() {
@@ -193,5 +606,355 @@ mod interpreter_test {
     }
 
     #[test]
-    fn test_screen() {}
+    fn test_scan() {
+        // Marks every third cell, then walks back and forth over the markers
+        let program = b">>>+>>>+>>>+[<<<]>>>[>>>]+";
+        let expected = [0, 0, 0, 1, 0, 0, 1, 0, 0, 1, 0, 0, 1];
+
+        let instructions = Parser::from_bytes(program).unwrap().optimized_parse(true);
+        assert!(instructions
+            .iter()
+            .any(|instruction| matches!(instruction, Instruction::Scan(-3))));
+        assert!(instructions
+            .iter()
+            .any(|instruction| matches!(instruction, Instruction::Scan(3))));
+
+        let mut interpreter = Interpreter::new(instructions);
+        interpreter.eval().unwrap();
+        assert_eq!(interpreter.tape.get_slice(0, 12).unwrap(), &expected);
+
+        let mut interpreter = Interpreter::new(Parser::from_bytes(program).unwrap().parse());
+        interpreter.eval().unwrap();
+        assert_eq!(interpreter.tape.get_slice(0, 12).unwrap(), &expected);
+    }
+
+    #[test]
+    fn test_balanced_loops() {
+        // Multiplies two cells away from the start without ever moving the pointer
+        let program = b">>+++++[>++<-]>>++[-<<+>+[-<<<+>>>]>]<<<<";
+        let expected = [12, 0, 2, 0, 0, 0];
+
+        let instructions = Parser::from_bytes(program).unwrap().optimized_parse(true);
+        assert!(!instructions
+            .iter()
+            .any(|instruction| matches!(instruction, Instruction::IncPtr(_))));
+        assert!(instructions
+            .iter()
+            .any(|instruction| matches!(instruction, Instruction::StartLoop(_, 2))));
+
+        let mut interpreter = Interpreter::new(instructions);
+        interpreter.eval().unwrap();
+        assert_eq!(interpreter.tape.get_slice(0, 5).unwrap(), &expected);
+
+        let mut interpreter = Interpreter::new(Parser::from_bytes(program).unwrap().parse());
+        interpreter.eval().unwrap();
+        assert_eq!(interpreter.tape.get_slice(0, 5).unwrap(), &expected);
+    }
+
+    #[test]
+    fn test_final_pointer() {
+        // The pointer offset carried past the last loop still moves the pointer
+        let program = b">>+++++[>++<-]>+>";
+
+        let mut interpreter =
+            Interpreter::new(Parser::from_bytes(program).unwrap().optimized_parse(true));
+        interpreter.eval().unwrap();
+        let mut reference = Interpreter::new(Parser::from_bytes(program).unwrap().parse());
+        reference.eval().unwrap();
+
+        assert_eq!(interpreter.tape.get_ptr(), 4);
+        assert_eq!(interpreter.tape.get_ptr(), reference.tape.get_ptr());
+        assert_eq!(
+            interpreter.tape.get_slice(0, 4).unwrap(),
+            reference.tape.get_slice(0, 4).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_guards() {
+        // Balanced loops are guarded along with the cell operations around them
+        let program = b">>+++++[>++<-]>>++[-<<+>+[-<<<+>>>]>]<<<<-";
+        let expected = [11, 0, 2, 0, 0, 0];
+
+        let instructions = Parser::from_bytes(program).unwrap().optimized_parse(false);
+        assert!(matches!(instructions[0], Instruction::Guard(_, 0, 4)));
+
+        let mut interpreter = Interpreter::new(instructions);
+        interpreter.eval().unwrap();
+        assert_eq!(interpreter.tape.get_slice(0, 5).unwrap(), &expected);
+
+        // Regions that leave the tape run checked
+        let program = b"+<->>+";
+        let instructions = Parser::from_bytes(program).unwrap().optimized_parse(false);
+        assert!(matches!(instructions[0], Instruction::Guard(3, -1, 1)));
+        assert!(Interpreter::new(instructions).eval().is_err());
+    }
+
+    #[test]
+    fn test_passes() {
+        let program = b">>+++++[>++<-]>>++[-<<+>+[-<<<+>>>]>]>>>+[<<<]>>>[>>>]+<<+>-<";
+        let mut reference = Interpreter::new(Parser::from_bytes(program).unwrap().parse());
+        reference.eval().unwrap();
+
+        let mut reordered = PassManager::from_passes(&Pass::ALL, false);
+        reordered.disable(Pass::Idiom).enable(Pass::Idiom);
+        assert_eq!(reordered.passes().last(), Some(&Pass::Idiom));
+
+        let mut managers: Vec<_> = (0..=3)
+            .map(|level| PassManager::new(level, false))
+            .collect();
+        managers.push(reordered);
+        managers.push(PassManager::from_passes(
+            &[Pass::Bounds, Pass::Order, Pass::Bounds],
+            false,
+        ));
+
+        for manager in managers {
+            let (instructions, stats) =
+                manager.run_with_stats(Parser::from_bytes(program).unwrap());
+            assert_eq!(stats.len(), manager.passes().len());
+
+            let mut interpreter = Interpreter::new(instructions);
+            interpreter.eval().unwrap();
+            assert_eq!(
+                interpreter.tape.get_slice(0, 15).unwrap(),
+                reference.tape.get_slice(0, 15).unwrap()
+            );
+        }
+
+        // Without batch optimization first, equal instructions follow each other
+        let program = b"++>>+<<->>>";
+        let mut reference = Interpreter::new(Parser::from_bytes(program).unwrap().parse());
+        reference.eval().unwrap();
+        for passes in [&[Pass::Redundancy][..], &[Pass::Order, Pass::Redundancy]] {
+            let manager = PassManager::from_passes(passes, false);
+            let mut interpreter =
+                Interpreter::new(manager.run(Parser::from_bytes(program).unwrap()));
+            interpreter.eval().unwrap();
+            assert_eq!(
+                interpreter.tape.get_slice(0, 3).unwrap(),
+                reference.tape.get_slice(0, 3).unwrap()
+            );
+        }
+
+        let (_, stats) = PassManager::from_passes(&[Pass::Batch], false)
+            .run_with_stats(Parser::from_bytes(b"+++>>-").unwrap());
+        assert_eq!((stats[0].before, stats[0].after), (6, 3));
+        assert_eq!((stats[0].removed, stats[0].rewritten), (5, 2));
+
+        assert_eq!(
+            "direct-cell-mod".parse::<Pass>().unwrap(),
+            Pass::DirectCellMod
+        );
+        assert!("unroll".parse::<Pass>().is_err());
+    }
+
+    /// Runs a program with and without optimizations and compares the register buffer and RAM
+    fn compare_optimizations(program: &[u8], len: usize) -> Vec<Instruction> {
+        let optimized = Parser::from_bytes(program).unwrap().optimized_parse(false);
+
+        let mut interpreter = Interpreter::new(optimized.clone());
+        interpreter.eval().unwrap();
+        let mut reference = Interpreter::new(Parser::from_bytes(program).unwrap().parse());
+        reference.eval().unwrap();
+
+        assert_eq!(
+            interpreter.tape.get_slice(0, len).unwrap(),
+            reference.tape.get_slice(0, len).unwrap()
+        );
+        optimized
+    }
+
+    #[test]
+    fn test_idioms() -> anyhow::Result<()> {
+        let mut coder = Coder::new(vec![]);
+        coder
+            .seek(pos::G0 + 3 * 300 - 2)?
+            .set_val(0x12)?
+            .inc_ptr()?
+            .set_val(0x34)?
+            .set_word(word::D, 0x01ff)?
+            .set_word(word::A, 300)?
+            .inc_word(word::D, [pos::VU, pos::VL])?
+            .copy_word(word::D, &[word::Q], pos::VU)?
+            .dec_word(word::Q, [pos::VU, pos::VL])?
+            .is_nonzero(word::Q, pos::FU, [pos::VU, pos::VL])?
+            .read_memory()?
+            .seek(0)?;
+
+        let instructions = compare_optimizations(coder.writer(), 1000);
+        let idioms: Vec<_> = instructions
+            .iter()
+            .filter_map(|instruction| match instruction {
+                Instruction::Idiom(idiom, _) => Some(*idiom),
+                _ => None,
+            })
+            .collect();
+
+        assert!(matches!(idioms[0], Idiom::IncWord { word: (3, 4), .. }));
+        assert!(matches!(idioms[1], Idiom::CopyWord { src: (3, 4), dest: (12, 13), temp: 21 }));
+        assert!(matches!(idioms[2], Idiom::DecWord { word: (12, 13), .. }));
+        assert!(matches!(idioms[3], Idiom::IsNonzero { word: (12, 13), dest: 18, .. }));
+        assert!(matches!(idioms[4], Idiom::ReadMemory { .. }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_idiom_preconditions() -> anyhow::Result<()> {
+        // Temporary cells are not clear, the original code has to run
+        let mut coder = Coder::new(vec![]);
+        coder
+            .seek(pos::VU)?
+            .inc_val()?
+            .set_word(word::D, 0x00ff)?
+            .inc_word(word::D, [pos::VU, pos::VL])?
+            .seek(pos::FU)?
+            .inc_val()?
+            .is_nonzero(word::D, pos::FU, [pos::VU, pos::VL])?
+            .seek(0)?;
+
+        let instructions = compare_optimizations(coder.writer(), 30);
+        assert!(instructions
+            .iter()
+            .any(|instruction| matches!(instruction, Instruction::Idiom(_, _))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_limits() {
+        let endless = Parser::from_bytes(b"+[>+<]").unwrap().optimized_parse(false);
+
+        let mut interpreter = Interpreter::new(endless.clone());
+        let limits = Limits {
+            max_steps: Some(1000),
+            timeout: None,
+        };
+        assert_eq!(interpreter.eval_limited(limits).unwrap(), Exit::MaxSteps);
+
+        let mut interpreter = Interpreter::new(endless.clone());
+        let limits = Limits {
+            max_steps: None,
+            timeout: Some(Duration::from_millis(10)),
+        };
+        assert_eq!(interpreter.eval_limited(limits).unwrap(), Exit::Timeout);
+
+        #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+        {
+            let mut interpreter = Interpreter::new(endless);
+            assert_eq!(interpreter.eval_jit(limits).unwrap(), Exit::Timeout);
+        }
+
+        let finite = Parser::from_bytes(b"++[>+<-]").unwrap().optimized_parse(false);
+        let mut interpreter = Interpreter::new(finite);
+        assert_eq!(interpreter.eval_limited(limits).unwrap(), Exit::Halted);
+    }
+
+    #[test]
+    fn test_observer() {
+        let endless = Parser::from_bytes(b"+[>+<]").unwrap().parse();
+        let limits = Limits {
+            max_steps: Some(1000),
+            timeout: None,
+        };
+
+        let mut calls = vec![];
+        let mut observer = Observer::new(300, |tape, steps| {
+            calls.push((steps, tape.get_slice(1, 1).unwrap()[0]));
+            Ok(())
+        });
+        let mut interpreter = Interpreter::new(endless);
+        let exit = interpreter.eval_observed(limits, Some(&mut observer));
+        assert_eq!(exit.unwrap(), Exit::MaxSteps);
+        drop(observer);
+        // Each iteration of the loop executes four instructions and increments the second cell
+        assert_eq!(calls, [(300, 75), (600, 150), (900, 225)]);
+
+        let mut observer = Observer::new(10, |_, _| anyhow::bail!("stop"));
+        let mut interpreter = Interpreter::new(Parser::from_bytes(b"+[>+<]").unwrap().parse());
+        assert!(interpreter
+            .eval_observed(limits, Some(&mut observer))
+            .is_err());
+    }
+
+    #[test]
+    fn test_key_script() {
+        let kbd_after = |script: &str, max_steps| {
+            let endless = Parser::from_bytes(b"+[>+<]").unwrap().parse();
+            let mut interpreter = Interpreter::new(endless);
+            interpreter.set_keys(&KeyScript::parse(script).unwrap());
+            let limits = Limits {
+                max_steps: Some(max_steps),
+                timeout: None,
+            };
+            assert_eq!(interpreter.eval_limited(limits).unwrap(), Exit::MaxSteps);
+            interpreter.tape.kbd()
+        };
+
+        // Keys are applied before the instruction after their step count
+        assert_eq!(kbd_after("100 press a", 99), 0);
+        assert_eq!(kbd_after("100 press a", 100), 'a' as u16);
+        assert_eq!(kbd_after("0 press a\n50 press left\n60 release left", 55), 130);
+        assert_eq!(kbd_after("0 press a\n50 press left\n60 release left", 60), 'a' as u16);
+
+        let mut interpreter = Interpreter::new(vec![]);
+        interpreter.set_keys(&KeyScript::parse("frame 1 press a").unwrap());
+        assert!(interpreter.eval_limited(Limits::default()).is_err());
+    }
+
+    /// Frontend pressing a key on its first poll and asking to stop on its third one
+    #[derive(Default)]
+    struct ScriptedFrontend {
+        polls: usize,
+        presented: usize,
+    }
+
+    impl Frontend for ScriptedFrontend {
+        fn present(&mut self, _screen: Screen) -> Result<()> {
+            self.presented += 1;
+            Ok(())
+        }
+
+        fn poll_input(&mut self, tape: &mut Tape) -> Result<()> {
+            self.polls += 1;
+            if self.polls == 1 {
+                tape.set_kbd('a' as u16);
+            }
+            Ok(())
+        }
+
+        fn should_quit(&self) -> bool {
+            self.polls == 3
+        }
+    }
+
+    #[test]
+    fn test_frontend() {
+        let endless = Parser::from_bytes(b"+[>+<]").unwrap().parse();
+        let mut interpreter = Interpreter::new(endless.clone());
+        interpreter.record_keys();
+        let mut frontend = ScriptedFrontend::default();
+        let exit = interpreter.run(&mut frontend, Limits::default(), None);
+        assert_eq!(exit.unwrap(), Exit::Closed);
+        assert_eq!(frontend.polls, 3);
+        assert_eq!(interpreter.tape.kbd(), 'a' as u16);
+        // The frontend is first polled once the clock interval has passed
+        let recording = interpreter.recorded_keys().unwrap().to_string();
+        assert_eq!(recording, format!("{} press a\n", CLOCK_INTERVAL - 1));
+
+        // The screen is shown once more when the program stops on its own
+        let mut interpreter = Interpreter::new(Parser::from_bytes(b"++").unwrap().parse());
+        let mut frontend = ScriptedFrontend::default();
+        let exit = interpreter.run(&mut frontend, Limits::default(), None);
+        assert_eq!(exit.unwrap(), Exit::Halted);
+        assert_eq!((frontend.polls, frontend.presented), (0, 1));
+
+        #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+        {
+            let mut interpreter = Interpreter::new(endless);
+            let mut frontend = ScriptedFrontend::default();
+            let exit = interpreter.run_jit(&mut frontend, Limits::default());
+            assert_eq!(exit.unwrap(), Exit::Closed);
+            assert_eq!(interpreter.tape.kbd(), 'a' as u16);
+        }
+    }
 }
@@ -0,0 +1,306 @@
+use super::consts::{RAM, REGISTER_BUFFER, SCREEN, TAPE_SIZE};
+use super::instruction::Instruction;
+
+use std::cmp::Ordering;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+/// Pretty-prints a program, one instruction per line with its index and loop nesting
+///
+/// # Arguments
+/// * `instructions` - Program to print, loops must be paired
+/// * `out` - Destination of the listing
+pub fn dump_ir(instructions: &[Instruction], out: &mut impl Write) -> io::Result<()> {
+    let width = instructions.len().to_string().len();
+    let mut depth = 0;
+
+    for (i, instruction) in instructions.iter().enumerate() {
+        if let Instruction::EndLoop(_, _) = instruction {
+            depth -= 1;
+        }
+
+        let text = match *instruction {
+            Instruction::IncPtr(batch) => format!("ptr += {batch}"),
+            Instruction::DecPtr(batch) => format!("ptr -= {batch}"),
+            Instruction::IncCell(batch, offset) => format!("cell[{offset:+}] += {batch}"),
+            Instruction::DecCell(batch, offset) => format!("cell[{offset:+}] -= {batch}"),
+            Instruction::StartLoop(end, offset) => format!("while cell[{offset:+}] {{  -> {end}"),
+            Instruction::EndLoop(start, offset) => format!("}} cell[{offset:+}]  -> {start}"),
+            Instruction::Scan(stride) => format!("scan {stride:+}"),
+            Instruction::Idiom(idiom, len) => format!("idiom {idiom:?}, replaces {len}"),
+            Instruction::Guard(len, min_offset, max_offset) => {
+                format!("guard cell[{min_offset:+}..={max_offset:+}], covers {len}")
+            }
+            Instruction::BreakPoint => "breakpoint".to_string(),
+        };
+        writeln!(out, "{i:>width$}  {}{text}", "  ".repeat(depth))?;
+
+        if let Instruction::StartLoop(_, _) = instruction {
+            depth += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Lowers a program back into brainfuck that any interpreter can run
+///
+/// Idioms and guards are dropped, the code they were recognized from is kept.
+pub fn emit_bf(instructions: &[Instruction]) -> Vec<u8> {
+    let mut code = vec![];
+    // Position of the brainfuck pointer relative to the memory pointer of the program
+    let mut at = 0;
+
+    for instruction in instructions {
+        match *instruction {
+            // Cells are addressed relative to the memory pointer, so the brainfuck pointer stays
+            Instruction::IncPtr(batch) => at -= batch as isize,
+            Instruction::DecPtr(batch) => at += batch as isize,
+            Instruction::IncCell(batch, offset) => {
+                seek(&mut code, &mut at, offset);
+                code.extend(vec![b'+'; batch as u8 as usize]);
+            }
+            Instruction::DecCell(batch, offset) => {
+                seek(&mut code, &mut at, offset);
+                code.extend(vec![b'-'; batch as u8 as usize]);
+            }
+            Instruction::StartLoop(_, offset) => {
+                seek(&mut code, &mut at, offset);
+                code.push(b'[');
+            }
+            Instruction::EndLoop(_, offset) => {
+                seek(&mut code, &mut at, offset);
+                code.push(b']');
+            }
+            Instruction::Scan(stride) => {
+                seek(&mut code, &mut at, 0);
+                let step = if stride >= 0 { b'>' } else { b'<' };
+                code.push(b'[');
+                code.extend(vec![step; stride.unsigned_abs()]);
+                code.push(b']');
+            }
+            Instruction::Idiom(_, _) | Instruction::Guard(_, _, _) => {}
+            Instruction::BreakPoint => {
+                seek(&mut code, &mut at, 0);
+                code.push(b'#');
+            }
+        }
+    }
+
+    // The program has to end on its memory pointer
+    seek(&mut code, &mut at, 0);
+    code
+}
+
+/// Lowers a program into a standalone C program that any C compiler can build
+///
+/// The program keeps the tape in a static array without bounds checks. When it exits or is
+/// interrupted, the screen is written as a PBM image to the path given as its first argument,
+/// `screen.pbm` by default. An interrupt stops the program at the next loop iteration, so the
+/// screen is written outside of the signal handler. Idioms and guards are dropped, the code they
+/// were recognized from is kept.
+pub fn emit_c(instructions: &[Instruction]) -> String {
+    let mut code = format!(
+        "#include <signal.h>\n\
+         #include <stdio.h>\n\
+         #include <stdlib.h>\n\
+         \n\
+         #define TAPE_SIZE {TAPE_SIZE}\n\
+         #define SCREEN {}\n\
+         #define SCREEN_WORDS {}\n\
+         \n\
+         static unsigned char tape[TAPE_SIZE];\n\
+         static const char *screen_path = \"screen.pbm\";\n\
+         static volatile sig_atomic_t interrupted = 0;\n\
+         \n\
+         static unsigned char reverse_bits(unsigned char byte) {{\n    \
+             unsigned char reversed = 0;\n    \
+             for (int bit = 0; bit < 8; bit++) reversed |= ((byte >> bit) & 1) << (7 - bit);\n    \
+             return reversed;\n\
+         }}\n\
+         \n\
+         /* Writes the screen as a 512x256 PBM image, pixels are read from the lowest bit of a word */\n\
+         static void write_screen(void) {{\n    \
+             FILE *file = fopen(screen_path, \"wb\");\n    \
+             if (!file) return;\n    \
+             fprintf(file, \"P4\\n512 256\\n\");\n    \
+             for (int word = 0; word < SCREEN_WORDS; word++) {{\n        \
+                 fputc(reverse_bits(tape[SCREEN + 3 * word + 1]), file);\n        \
+                 fputc(reverse_bits(tape[SCREEN + 3 * word]), file);\n    \
+             }}\n    \
+             fclose(file);\n\
+         }}\n\
+         \n\
+         /* Only sets a flag, the loops stop and main returns so the screen is written at exit */\n\
+         static void interrupt(int signal) {{\n    \
+             (void)signal;\n    \
+             interrupted = 1;\n\
+         }}\n\
+         \n\
+         int main(int argc, char **argv) {{\n    \
+             if (argc > 1) screen_path = argv[1];\n    \
+             atexit(write_screen);\n    \
+             signal(SIGINT, interrupt);\n\
+         \n    \
+             unsigned char *p = tape;\n",
+        REGISTER_BUFFER + RAM,
+        SCREEN / 3
+    );
+
+    let mut depth = 1;
+    let mut has_loops = false;
+    for instruction in instructions {
+        if let Instruction::EndLoop(_, _) = instruction {
+            depth -= 1;
+        }
+        let indent = "    ".repeat(depth);
+
+        match *instruction {
+            Instruction::IncPtr(batch) => writeln!(code, "{indent}p += {batch};"),
+            Instruction::DecPtr(batch) => writeln!(code, "{indent}p -= {batch};"),
+            Instruction::IncCell(batch, offset) => {
+                writeln!(code, "{indent}p[{offset}] += {};", batch as u8)
+            }
+            Instruction::DecCell(batch, offset) => {
+                writeln!(code, "{indent}p[{offset}] -= {};", batch as u8)
+            }
+            // The condition is checked on different cells when entering and repeating a loop
+            Instruction::StartLoop(end, offset) => match instructions[end] {
+                Instruction::EndLoop(_, end_offset) if end_offset == offset => {
+                    writeln!(code, "{indent}while (p[{offset}]) {{")
+                }
+                _ => writeln!(code, "{indent}if (p[{offset}]) do {{"),
+            },
+            Instruction::EndLoop(start, offset) => match instructions[start] {
+                Instruction::StartLoop(_, start_offset) if start_offset == offset => {
+                    writeln!(code, "{indent}}}")
+                }
+                _ => writeln!(code, "{indent}}} while (p[{offset}]);"),
+            },
+            Instruction::Scan(stride) if stride >= 0 => {
+                writeln!(code, "{indent}while (*p) p += {stride};")
+            }
+            Instruction::Scan(stride) => {
+                writeln!(code, "{indent}while (*p) p -= {};", stride.unsigned_abs())
+            }
+            Instruction::Idiom(_, _) | Instruction::Guard(_, _, _) => Ok(()),
+            Instruction::BreakPoint => writeln!(code, "{indent}/* breakpoint */"),
+        }
+        .unwrap();
+
+        if let Instruction::StartLoop(_, _) = instruction {
+            depth += 1;
+            has_loops = true;
+            writeln!(code, "{indent}    if (interrupted) goto stop;").unwrap();
+        }
+    }
+
+    if has_loops {
+        code.push_str("stop:\n");
+    }
+    code.push_str("    return interrupted ? 130 : 0;\n}\n");
+    code
+}
+
+/// Moves the brainfuck pointer to a cell relative to the memory pointer
+fn seek(code: &mut Vec<u8>, at: &mut isize, offset: isize) {
+    match offset.cmp(at) {
+        Ordering::Greater => code.extend(vec![b'>'; (offset - *at) as usize]),
+        Ordering::Less => code.extend(vec![b'<'; (*at - offset) as usize]),
+        Ordering::Equal => {}
+    }
+    *at = offset;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hackfuck::interpreter::Interpreter;
+    use crate::hackfuck::parser::Parser;
+    use std::process::{self, Command};
+    use std::{env, fs};
+
+    #[test]
+    fn dump() {
+        let instructions = Parser::from_bytes(b">>+[-<+>]<<")
+            .unwrap()
+            .optimized_parse(false);
+        let mut out = vec![];
+        dump_ir(&instructions, &mut out).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "0  guard cell[+1..=+2], covers 5\n\
+             1  cell[+2] += 1\n\
+             2  while cell[+2] {  -> 5\n\
+             3    cell[+2] -= 1\n\
+             4    cell[+1] += 1\n\
+             5  } cell[+2]  -> 2\n"
+        );
+    }
+
+    #[test]
+    fn emit_c_loops() {
+        let instructions = Parser::from_bytes(b"+[->>+<<]>>[<]")
+            .unwrap()
+            .optimized_parse(false);
+        let code = emit_c(&instructions);
+
+        assert!(code.contains("    unsigned char *p = tape;\n"));
+        assert!(code.contains(
+            "    p[0] += 1;\n    \
+             while (p[0]) {\n        \
+             if (interrupted) goto stop;\n        \
+             p[0] -= 1;\n        \
+             p[2] += 1;\n    \
+             }\n    \
+             p += 2;\n    \
+             while (*p) p -= 1;\n\
+             stop:\n    \
+             return interrupted ? 130 : 0;\n}\n"
+        ));
+    }
+
+    #[test]
+    fn emit_c_compiles() {
+        let dir = env::temp_dir().join(format!("brainhack_emit_c_{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        // Sets the upper byte of the first and the lower byte of the second screen word, the C
+        // program has to draw them at the same pixels as the interpreter
+        let mut program = b">".repeat(REGISTER_BUFFER + RAM);
+        program.extend(b"++++++++[->>>>++++<<<<]-");
+        let instructions = Parser::from_bytes(&program).unwrap().optimized_parse(false);
+        fs::write(dir.join("program.c"), emit_c(&instructions)).unwrap();
+
+        // Skipped on machines without a C compiler
+        let Ok(status) = Command::new("cc")
+            .current_dir(&dir)
+            .args(["-o", "program", "program.c"])
+            .status()
+        else {
+            return;
+        };
+        assert!(status.success());
+        let status = Command::new(dir.join("program"))
+            .arg(dir.join("screen.pbm"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let image = fs::read(dir.join("screen.pbm")).unwrap();
+        assert_eq!(&image[..13], b"P4\n512 256\n\x00\xff");
+        assert_eq!(&image[13..15], b"\x04\x00");
+        let mut interpreter = Interpreter::new(instructions);
+        interpreter.eval().unwrap();
+        assert_eq!(image, interpreter.tape.screen().to_pbm());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn emit() {
+        let instructions = Parser::from_bytes(b">>+[-<+>]<<[>>>]+")
+            .unwrap()
+            .optimized_parse(false);
+        assert_eq!(emit_bf(&instructions), b">>+[-<+>]<<[>>>]+");
+    }
+}
@@ -0,0 +1,697 @@
+use super::consts::TAPE_SIZE;
+use super::idiom::Idiom;
+use super::instruction::Instruction;
+use super::tape::Tape;
+
+use anyhow::{bail, ensure, Error, Result};
+use std::collections::HashMap;
+use std::{mem, ptr};
+
+/// Number of loop iterations between calls of the tick callback
+const TICK_INTERVAL: u32 = 1 << 16;
+
+// Reasons the compiled code returns for, in rax
+const FINISHED: u64 = 0;
+const PTR_OVERFLOW: u64 = 1;
+const PTR_UNDERFLOW: u64 = 2;
+const OUT_OF_BOUNDS: u64 = 3;
+const STOPPED: u64 = 4;
+const HOOK_ERROR: u64 = 5;
+
+/// Signature of the compiled code, called with the context, the first cell and the memory pointer
+type Entry = unsafe extern "sysv64" fn(*mut Context<'_>, *mut u8, usize) -> u64;
+
+/// Program compiled into native x86-64 code
+///
+/// The compiled code keeps the memory pointer in rbx and the first cell in r12, performs the
+/// same bounds checks as `Interpreter::eval`, and calls back into Rust for idioms,
+/// breakpoints and a periodic tick.
+pub struct Jit {
+    code: ExecutableBuffer,
+    idioms: Vec<Idiom>,
+}
+
+impl Jit {
+    /// Compiles a program
+    ///
+    /// # Arguments
+    /// * `instructions` - Program to compile, loops must be paired
+    ///
+    /// # Returns
+    /// Returns Err if the program can't be compiled or mapped as executable memory
+    pub fn compile(instructions: &[Instruction]) -> Result<Self> {
+        let mut compiler = Compiler::default();
+        compiler.program(instructions)?;
+
+        Ok(Self {
+            code: ExecutableBuffer::new(&compiler.asm.code)?,
+            idioms: compiler.idioms,
+        })
+    }
+
+    /// Runs the program on a tape
+    ///
+    /// # Arguments
+    /// * `tape` - Tape the program runs on, starting at its memory pointer
+    /// * `tick` - Called every few loop iterations, stops the program by returning false
+    ///
+    /// # Returns
+    /// Returns false if the tick callback stopped the program before it finished
+    pub fn run(&self, tape: &mut Tape, tick: &mut dyn FnMut(&mut Tape) -> bool) -> Result<bool> {
+        let tape: *mut Tape = tape;
+        // SAFETY: the tape outlives the call, hooks access it only while the compiled code waits
+        let (base, mem_ptr) = unsafe { ((*tape).as_mut_ptr(), (*tape).get_ptr()) };
+        let mut context = Context {
+            mem_ptr,
+            tape,
+            idioms: &self.idioms,
+            tick,
+            error: None,
+        };
+
+        // SAFETY: the code was generated by `Compiler` for this signature
+        let status = unsafe {
+            let entry: Entry = mem::transmute(self.code.ptr);
+            let status = entry(&mut context, base, mem_ptr);
+            (*tape).set_ptr(context.mem_ptr);
+            status
+        };
+
+        match status {
+            FINISHED => Ok(true),
+            STOPPED => Ok(false),
+            PTR_OVERFLOW => bail!("Memory pointer overflow"),
+            PTR_UNDERFLOW => bail!("Memory pointer underflow"),
+            OUT_OF_BOUNDS => bail!("Memory pointer out of bounds"),
+            HOOK_ERROR => Err(context.error.take().unwrap()),
+            _ => unreachable!("Unknown status {status}"),
+        }
+    }
+}
+
+/// State shared between `Jit::run` and the hooks called by the compiled code
+#[repr(C)]
+struct Context<'a> {
+    /// Memory pointer, written by the compiled code when it returns and by hooks moving it
+    mem_ptr: usize,
+    tape: *mut Tape,
+    idioms: &'a [Idiom],
+    tick: &'a mut dyn FnMut(&mut Tape) -> bool,
+    error: Option<Error>,
+}
+
+/// Performs an idiom, returns 1 if it was applied, 0 if its code has to run and 2 on errors
+extern "sysv64" fn idiom_hook(context: *mut Context<'_>, mem_ptr: usize, idiom: usize) -> u64 {
+    // SAFETY: called by the compiled code with the context it was given
+    let context = unsafe { &mut *context };
+    let tape = unsafe { &mut *context.tape };
+
+    tape.set_ptr(mem_ptr);
+    let applied = tape.idiom(context.idioms[idiom]);
+    context.mem_ptr = tape.get_ptr();
+
+    match applied {
+        Ok(applied) => applied as u64,
+        Err(err) => {
+            context.error = Some(err);
+            2
+        }
+    }
+}
+
+/// Pauses the program at a breakpoint
+extern "sysv64" fn breakpoint_hook(context: *mut Context<'_>, mem_ptr: usize) {
+    // SAFETY: called by the compiled code with the context it was given
+    let tape = unsafe { &mut *(*context).tape };
+
+    tape.set_ptr(mem_ptr);
+    tape.breakpoint();
+}
+
+/// Calls the tick callback, returns 1 if the program has to stop
+extern "sysv64" fn tick_hook(context: *mut Context<'_>, mem_ptr: usize) -> u64 {
+    // SAFETY: called by the compiled code with the context it was given
+    let context = unsafe { &mut *context };
+    let tape = unsafe { &mut *context.tape };
+
+    tape.set_ptr(mem_ptr);
+    !(context.tick)(tape) as u64
+}
+
+/// Position in the code that jumps can target
+type Label = usize;
+
+/// Condition codes of conditional jumps
+#[derive(Copy, Clone)]
+enum Cond {
+    Below = 0x2,
+    AboveEqual = 0x3,
+    Equal = 0x4,
+    NotEqual = 0x5,
+    Above = 0x7,
+    Sign = 0x8,
+}
+
+/// Machine code buffer with labels for jumps
+#[derive(Default)]
+struct Assembler {
+    code: Vec<u8>,
+    /// Position each label is bound to, if bound
+    bound: Vec<Option<usize>>,
+    /// Positions of relative offsets waiting for a label to be bound
+    pending: HashMap<Label, Vec<usize>>,
+}
+
+impl Assembler {
+    fn emit(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    fn emit_i32(&mut self, value: i32) {
+        self.emit(&value.to_le_bytes());
+    }
+
+    fn label(&mut self) -> Label {
+        self.bound.push(None);
+        self.bound.len() - 1
+    }
+
+    /// Binds a label to the current position, resolving jumps waiting for it
+    fn bind(&mut self, label: Label) {
+        let target = self.code.len();
+        self.bound[label] = Some(target);
+        for at in self.pending.remove(&label).unwrap_or_default() {
+            self.patch(at, target);
+        }
+    }
+
+    fn patch(&mut self, at: usize, target: usize) {
+        let rel = target as i64 - (at as i64 + 4);
+        self.code[at..at + 4].copy_from_slice(&(rel as i32).to_le_bytes());
+    }
+
+    /// Emits a relative offset to a label, bound already or later
+    fn rel32(&mut self, label: Label) {
+        let at = self.code.len();
+        self.emit_i32(0);
+        match self.bound[label] {
+            Some(target) => self.patch(at, target),
+            None => self.pending.entry(label).or_default().push(at),
+        }
+    }
+
+    fn jmp(&mut self, label: Label) {
+        self.emit(&[0xe9]);
+        self.rel32(label);
+    }
+
+    fn jcc(&mut self, cond: Cond, label: Label) {
+        self.emit(&[0x0f, 0x80 + cond as u8]);
+        self.rel32(label);
+    }
+
+    fn call(&mut self, label: Label) {
+        self.emit(&[0xe8]);
+        self.rel32(label);
+    }
+
+    /// Calls a Rust function with the context, the memory pointer and an argument
+    fn call_hook(&mut self, hook: usize, arg: usize) {
+        // mov rdi, r14; mov rsi, rbx
+        self.emit(&[0x4c, 0x89, 0xf7, 0x48, 0x89, 0xde]);
+        // mov rdx, arg
+        self.emit(&[0x48, 0xba]);
+        self.emit(&(arg as u64).to_le_bytes());
+        // mov rax, hook; call rax
+        self.emit(&[0x48, 0xb8]);
+        self.emit(&(hook as u64).to_le_bytes());
+        self.emit(&[0xff, 0xd0]);
+    }
+}
+
+/// Translates instructions into machine code
+#[derive(Default)]
+struct Compiler {
+    asm: Assembler,
+    idioms: Vec<Idiom>,
+    /// Labels of the exits reporting a status
+    exits: Vec<(Label, u64)>,
+    tick: Label,
+}
+
+impl Compiler {
+    /// Label of the exit reporting a status
+    fn exit(&mut self, status: u64) -> Label {
+        match self.exits.iter().find(|(_, exit)| *exit == status) {
+            Some(&(label, _)) => label,
+            None => {
+                let label = self.asm.label();
+                self.exits.push((label, status));
+                label
+            }
+        }
+    }
+
+    fn program(&mut self, instructions: &[Instruction]) -> Result<()> {
+        self.tick = self.asm.label();
+        let exit = self.asm.label();
+
+        // push rbx; push r12; push r13; push r14; push r15, r13 keeps the stack aligned
+        self.asm
+            .emit(&[0x53, 0x41, 0x54, 0x41, 0x55, 0x41, 0x56, 0x41, 0x57]);
+        // mov r14, rdi; mov r12, rsi; mov rbx, rdx
+        self.asm
+            .emit(&[0x49, 0x89, 0xfe, 0x49, 0x89, 0xf4, 0x48, 0x89, 0xd3]);
+        self.reset_tick();
+
+        self.range(instructions, 0, instructions.len(), true)?;
+
+        // xor eax, eax
+        self.asm.emit(&[0x31, 0xc0]);
+        self.asm.bind(exit);
+        // mov [r14], rbx
+        self.asm.emit(&[0x49, 0x89, 0x1e]);
+        // pop r15; pop r14; pop r13; pop r12; pop rbx; ret
+        self.asm
+            .emit(&[0x41, 0x5f, 0x41, 0x5e, 0x41, 0x5d, 0x41, 0x5c, 0x5b, 0xc3]);
+
+        for (label, status) in mem::take(&mut self.exits) {
+            self.asm.bind(label);
+            // mov eax, status
+            self.asm.emit(&[0xb8]);
+            self.asm.emit_i32(status as i32);
+            self.asm.jmp(exit);
+        }
+
+        // Tick subroutine, returns nonzero in rax if the program has to stop
+        self.asm.bind(self.tick);
+        // sub rsp, 8
+        self.asm.emit(&[0x48, 0x83, 0xec, 0x08]);
+        self.asm.call_hook(tick_hook as *const () as usize, 0);
+        // add rsp, 8
+        self.asm.emit(&[0x48, 0x83, 0xc4, 0x08]);
+        self.reset_tick();
+        self.asm.emit(&[0xc3]);
+
+        Ok(())
+    }
+
+    fn reset_tick(&mut self) {
+        // mov r15d, TICK_INTERVAL
+        self.asm.emit(&[0x41, 0xbf]);
+        self.asm.emit(&TICK_INTERVAL.to_le_bytes());
+    }
+
+    /// Compiles part of a program
+    ///
+    /// # Arguments
+    /// * `instructions` - Program containing the part, loop indices are absolute
+    /// * `start` - Index of the first instruction
+    /// * `end` - Index after the last instruction
+    /// * `checked` - Cell accesses are bounds checked if set, see `Tape::in_bounds`
+    fn range(
+        &mut self,
+        instructions: &[Instruction],
+        start: usize,
+        end: usize,
+        checked: bool,
+    ) -> Result<()> {
+        // Body and exit labels of the open loops
+        let mut loops = vec![];
+        // Labels of idioms that skip to an instruction
+        let mut skips: HashMap<usize, Vec<Label>> = HashMap::new();
+
+        let mut i = start;
+        while i < end {
+            for label in skips.remove(&i).unwrap_or_default() {
+                self.asm.bind(label);
+            }
+
+            match instructions[i] {
+                Instruction::IncPtr(batch) => {
+                    let overflow = self.exit(PTR_OVERFLOW);
+                    if batch > TAPE_SIZE {
+                        self.asm.jmp(overflow);
+                    } else {
+                        // Like `Tape::inc_ptr`, the memory pointer doesn't move on errors
+                        // lea rax, [rbx + batch]; cmp rax, TAPE_SIZE
+                        self.asm.emit(&[0x48, 0x8d, 0x83]);
+                        self.asm.emit_i32(batch as i32);
+                        self.asm.emit(&[0x48, 0x3d]);
+                        self.asm.emit_i32(TAPE_SIZE as i32);
+                        self.asm.jcc(Cond::Above, overflow);
+                        // mov rbx, rax
+                        self.asm.emit(&[0x48, 0x89, 0xc3]);
+                    }
+                }
+                Instruction::DecPtr(batch) => {
+                    let underflow = self.exit(PTR_UNDERFLOW);
+                    if batch > TAPE_SIZE {
+                        self.asm.jmp(underflow);
+                    } else {
+                        // cmp rbx, batch
+                        self.asm.emit(&[0x48, 0x81, 0xfb]);
+                        self.asm.emit_i32(batch as i32);
+                        self.asm.jcc(Cond::Below, underflow);
+                        // sub rbx, batch
+                        self.asm.emit(&[0x48, 0x81, 0xeb]);
+                        self.asm.emit_i32(batch as i32);
+                    }
+                }
+                // add byte [cell], batch
+                Instruction::IncCell(batch, offset) => {
+                    self.cell_op(0, offset, batch as u8, checked)
+                }
+                // sub byte [cell], batch
+                Instruction::DecCell(batch, offset) => {
+                    self.cell_op(5, offset, batch as u8, checked)
+                }
+                Instruction::StartLoop(_, offset) => {
+                    let (body, exit) = (self.asm.label(), self.asm.label());
+                    // cmp byte [cell], 0
+                    self.cell_op(7, offset, 0, checked);
+                    self.asm.jcc(Cond::Equal, exit);
+                    self.asm.bind(body);
+                    loops.push((body, exit));
+                }
+                Instruction::EndLoop(_, offset) => {
+                    let (body, exit) = loops.pop().unwrap();
+                    self.cell_op(7, offset, 0, checked);
+                    self.asm.jcc(Cond::Equal, exit);
+
+                    // dec r15d
+                    self.asm.emit(&[0x41, 0xff, 0xcf]);
+                    self.asm.jcc(Cond::NotEqual, body);
+                    self.asm.call(self.tick);
+                    // test rax, rax
+                    self.asm.emit(&[0x48, 0x85, 0xc0]);
+                    let stopped = self.exit(STOPPED);
+                    self.asm.jcc(Cond::NotEqual, stopped);
+                    self.asm.jmp(body);
+
+                    self.asm.bind(exit);
+                }
+                Instruction::Scan(stride) => self.scan(stride)?,
+                Instruction::Idiom(idiom, len) => {
+                    self.idioms.push(idiom);
+                    self.asm
+                        .call_hook(idiom_hook as *const () as usize, self.idioms.len() - 1);
+                    // mov rbx, [r14], the idiom may have moved the memory pointer
+                    self.asm.emit(&[0x49, 0x8b, 0x1e]);
+
+                    // cmp rax, 1
+                    self.asm.emit(&[0x48, 0x83, 0xf8, 0x01]);
+                    let skip = self.asm.label();
+                    self.asm.jcc(Cond::Equal, skip);
+                    skips.entry(i + 1 + len).or_default().push(skip);
+                    let error = self.exit(HOOK_ERROR);
+                    self.asm.jcc(Cond::Above, error);
+                }
+                Instruction::Guard(len, min_offset, max_offset) => {
+                    self.guard(instructions, i + 1, i + 1 + len, min_offset, max_offset)?;
+                    i += len;
+                }
+                Instruction::BreakPoint => {
+                    self.asm.call_hook(breakpoint_hook as *const () as usize, 0)
+                }
+            }
+            i += 1;
+        }
+
+        ensure!(loops.is_empty(), "Found unclosed loop");
+        for label in skips.remove(&end).unwrap_or_default() {
+            self.asm.bind(label);
+        }
+        ensure!(skips.is_empty(), "Idiom skips past its region");
+        Ok(())
+    }
+
+    /// Compiles an instruction operating on a byte relative to the memory pointer
+    ///
+    /// # Arguments
+    /// * `op` - Opcode extension of the `80 /op ib` instruction, 0 adds, 5 subtracts, 7 compares
+    /// * `offset` - Offset from the memory pointer
+    /// * `imm` - Immediate operand
+    /// * `checked` - Jumps to the out of bounds exit if the cell isn't on the tape
+    fn cell_op(&mut self, op: u8, offset: isize, imm: u8, checked: bool) {
+        let Ok(offset) = i32::try_from(offset) else {
+            // Cells this far from the memory pointer are never on the tape
+            let out_of_bounds = self.exit(OUT_OF_BOUNDS);
+            self.asm.jmp(out_of_bounds);
+            return;
+        };
+
+        if checked {
+            // lea rax, [rbx + offset]; cmp rax, TAPE_SIZE
+            self.asm.emit(&[0x48, 0x8d, 0x83]);
+            self.asm.emit_i32(offset);
+            self.asm.emit(&[0x48, 0x3d]);
+            self.asm.emit_i32(TAPE_SIZE as i32);
+            // Negative cells compare as large unsigned numbers
+            let out_of_bounds = self.exit(OUT_OF_BOUNDS);
+            self.asm.jcc(Cond::AboveEqual, out_of_bounds);
+            // op byte [r12 + rax], imm
+            self.asm.emit(&[0x41, 0x80, op << 3 | 0x04, 0x04, imm]);
+        } else {
+            // op byte [r12 + rbx + offset], imm
+            self.asm.emit(&[0x41, 0x80, 0x80 | op << 3 | 0x04, 0x1c]);
+            self.asm.emit_i32(offset);
+            self.asm.emit(&[imm]);
+        }
+    }
+
+    /// Compiles a scan, the memory pointer moves by a stride until it lands on a zero cell
+    ///
+    /// Like `Tape::scan`, the memory pointer is back where the scan started if it fails
+    fn scan(&mut self, stride: isize) -> Result<()> {
+        let step = match i32::try_from(stride.unsigned_abs()) {
+            Ok(step) if step > 0 => step,
+            _ => bail!("Cannot compile scan with stride {stride}"),
+        };
+        let (top, fail, done) = (self.asm.label(), self.asm.label(), self.asm.label());
+        // Scans that start after the last cell fail right away, like reading that cell does
+        let out_of_bounds = self.exit(OUT_OF_BOUNDS);
+        let fails = self.exit(if stride > 0 {
+            PTR_OVERFLOW
+        } else {
+            PTR_UNDERFLOW
+        });
+
+        // mov rcx, rbx
+        self.asm.emit(&[0x48, 0x89, 0xd9]);
+        // cmp rbx, TAPE_SIZE
+        self.asm.emit(&[0x48, 0x81, 0xfb]);
+        self.asm.emit_i32(TAPE_SIZE as i32);
+        self.asm.jcc(Cond::AboveEqual, out_of_bounds);
+
+        self.asm.bind(top);
+        self.cell_op(7, 0, 0, false);
+        self.asm.jcc(Cond::Equal, done);
+
+        // Scans to the right fail once they leave the tape, scans to the left once they pass the
+        // first cell
+        if stride > 0 {
+            // add rbx, step
+            self.asm.emit(&[0x48, 0x81, 0xc3]);
+            self.asm.emit_i32(step);
+            // cmp rbx, TAPE_SIZE
+            self.asm.emit(&[0x48, 0x81, 0xfb]);
+            self.asm.emit_i32(TAPE_SIZE as i32);
+            self.asm.jcc(Cond::AboveEqual, fail);
+        } else {
+            // sub rbx, step
+            self.asm.emit(&[0x48, 0x81, 0xeb]);
+            self.asm.emit_i32(step);
+            self.asm.jcc(Cond::Below, fail);
+        }
+        self.asm.jmp(top);
+
+        self.asm.bind(fail);
+        // mov rbx, rcx
+        self.asm.emit(&[0x48, 0x89, 0xcb]);
+        self.asm.jmp(fails);
+
+        self.asm.bind(done);
+        Ok(())
+    }
+
+    /// Compiles a guarded region twice, unchecked code runs if the whole region is on the tape
+    fn guard(
+        &mut self,
+        instructions: &[Instruction],
+        start: usize,
+        end: usize,
+        min_offset: isize,
+        max_offset: isize,
+    ) -> Result<()> {
+        let (checked, done) = (self.asm.label(), self.asm.label());
+
+        if let (Ok(min_offset), Ok(max_offset)) =
+            (i32::try_from(min_offset), i32::try_from(max_offset))
+        {
+            // lea rax, [rbx + min_offset]; test rax, rax
+            self.asm.emit(&[0x48, 0x8d, 0x83]);
+            self.asm.emit_i32(min_offset);
+            self.asm.emit(&[0x48, 0x85, 0xc0]);
+            self.asm.jcc(Cond::Sign, checked);
+            // lea rax, [rbx + max_offset]; cmp rax, TAPE_SIZE
+            self.asm.emit(&[0x48, 0x8d, 0x83]);
+            self.asm.emit_i32(max_offset);
+            self.asm.emit(&[0x48, 0x3d]);
+            self.asm.emit_i32(TAPE_SIZE as i32);
+            self.asm.jcc(Cond::AboveEqual, checked);
+
+            self.range(instructions, start, end, false)?;
+            self.asm.jmp(done);
+        }
+
+        self.asm.bind(checked);
+        self.range(instructions, start, end, true)?;
+        self.asm.bind(done);
+        Ok(())
+    }
+}
+
+/// Memory mapped readable and executable, unmapped when dropped
+struct ExecutableBuffer {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+impl ExecutableBuffer {
+    fn new(code: &[u8]) -> Result<Self> {
+        let len = code.len().max(1);
+
+        // SAFETY: a fresh private mapping is written before it is made executable
+        unsafe {
+            let ptr = libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if ptr == libc::MAP_FAILED {
+                bail!("Cannot map memory for compiled code");
+            }
+            let buffer = Self { ptr, len };
+
+            ptr::copy_nonoverlapping(code.as_ptr(), ptr as *mut u8, code.len());
+            if libc::mprotect(ptr, len, libc::PROT_READ | libc::PROT_EXEC) != 0 {
+                bail!("Cannot make compiled code executable");
+            }
+            Ok(buffer)
+        }
+    }
+}
+
+impl Drop for ExecutableBuffer {
+    fn drop(&mut self) {
+        // SAFETY: the mapping was created by `ExecutableBuffer::new` and is no longer used
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hackfuck::{interpreter::Interpreter, parser::Parser};
+    use crate::prelude::*;
+
+    /// Runs a program with `Interpreter::eval` and the JIT, both must end in the same state, even
+    /// after an error
+    fn compare(program: &[u8]) {
+        let instructions = Parser::from_bytes(program).unwrap().optimized_parse(false);
+
+        let mut reference = Interpreter::new(instructions.clone());
+        let expected = reference.eval().map_err(|err| err.to_string());
+
+        let mut tape = Tape::new();
+        let jit = Jit::compile(&instructions).unwrap();
+        let result = jit
+            .run(&mut tape, &mut |_| true)
+            .map(|_| ())
+            .map_err(|err| err.to_string());
+
+        assert_eq!(result, expected);
+        assert_eq!(tape.get_ptr(), reference.tape.get_ptr());
+        assert_eq!(
+            tape.get_slice(0, TAPE_SIZE - 1),
+            reference.tape.get_slice(0, TAPE_SIZE - 1)
+        );
+    }
+
+    #[test]
+    fn matches_eval() {
+        compare(b"+>++++++>->>>>>--------");
+        compare(b"++++[->+++<]>[-<++>]");
+        compare(b">>+++[>+>++<<-]>[-]>[>>>]");
+        compare(b">>>>>>>>+[<<<]<<++");
+        compare(b"++[>+[>+<-]<-]>>");
+    }
+
+    #[test]
+    fn matches_eval_idioms() -> anyhow::Result<()> {
+        let mut coder = Coder::new(vec![]);
+        coder
+            .set_word(word::D, 0x00ff)?
+            .inc_word(word::D, [pos::VU, pos::VL])?
+            .copy_word(word::D, &[word::Q], pos::VU)?
+            .dec_word(word::Q, [pos::VU, pos::VL])?
+            .is_nonzero(word::Q, pos::FU, [pos::VU, pos::VL])?
+            .read_memory()?
+            .seek(0)?;
+        compare(coder.writer());
+
+        // Temporary cells are not clear, the original code has to run
+        let mut coder = Coder::new(vec![]);
+        coder
+            .seek(pos::VU)?
+            .inc_val()?
+            .set_word(word::D, 0x00ff)?
+            .inc_word(word::D, [pos::VU, pos::VL])?
+            .seek(0)?;
+        compare(coder.writer());
+        Ok(())
+    }
+
+    #[test]
+    fn matches_eval_errors() {
+        compare(b"<");
+        compare(b"+[<+]");
+        compare(b"+[<<<]");
+        compare(b">>+[<<<]");
+        compare(&b">".repeat(TAPE_SIZE + 1));
+
+        // The memory pointer stays where the failing instruction found it
+        compare(b"+[>+]");
+        compare(b">>+<-[<-]");
+        let mut right = b">".repeat(TAPE_SIZE - 8);
+        right.extend(b"+>+>+>+>+>+>+>+<<<<<<[>>]");
+        compare(&right);
+        let mut end = b">".repeat(TAPE_SIZE);
+        end.extend(b"[<]");
+        compare(&end);
+        let mut end = b">".repeat(TAPE_SIZE);
+        end.extend(b"[>]");
+        compare(&end);
+    }
+
+    #[test]
+    fn tick_stops() {
+        let jit = Jit::compile(&Parser::from_bytes(b"+[>+<]").unwrap().parse()).unwrap();
+        let mut ticks = 0;
+        let finished = jit
+            .run(&mut Tape::new(), &mut |_| {
+                ticks += 1;
+                ticks < 3
+            })
+            .unwrap();
+
+        assert!(!finished);
+        assert_eq!(ticks, 3);
+    }
+}
@@ -0,0 +1,168 @@
+use sdl2::keyboard::Keycode;
+
+/// Keys held down, KBD reads the one pressed last
+#[derive(Debug, Default)]
+pub struct Keyboard {
+    /// Held keys and their Hack codes, in the order they were pressed
+    held: Vec<(Keycode, u16)>,
+    /// Key pressed last, the text typed next comes from it
+    last_pressed: Option<Keycode>,
+}
+
+impl Keyboard {
+    /// Returns the value of KBD, 0 when no key is held
+    pub fn code(&self) -> u16 {
+        self.held.last().map_or(0, |&(_, code)| code)
+    }
+
+    /// Holds a key, printable keys read as unshifted until their text arrives
+    ///
+    /// # Arguments
+    /// * `keycode` - Key pressed, repeats of a held key are ignored
+    pub fn key_down(&mut self, keycode: Keycode) {
+        if self.held.iter().any(|&(held, _)| held == keycode) {
+            return;
+        }
+        self.last_pressed = Some(keycode);
+        if let Some(code) = hack_code(keycode) {
+            self.held.push((keycode, code));
+        }
+    }
+
+    /// Releases a key
+    pub fn key_up(&mut self, keycode: Keycode) {
+        self.held.retain(|&(held, _)| held != keycode);
+    }
+
+    /// Sets the code of the last key pressed to the character it typed
+    ///
+    /// # Arguments
+    /// * `text` - Text typed, with shift and the keyboard layout applied
+    pub fn text_input(&mut self, text: &str) {
+        let Some(character) = text.chars().last().filter(|c| (' '..='~').contains(c)) else {
+            return;
+        };
+        match self.held.last_mut() {
+            Some((keycode, code)) if Some(*keycode) == self.last_pressed => {
+                *code = character as u16
+            }
+            _ => {}
+        }
+    }
+
+    /// Releases every key, for example when the window loses focus
+    pub fn clear(&mut self) {
+        self.held.clear();
+        self.last_pressed = None;
+    }
+}
+
+/// Returns the Hack code of a key, printable keys give the character they type without shift
+fn hack_code(keycode: Keycode) -> Option<u16> {
+    let code = match keycode {
+        Keycode::Return | Keycode::KpEnter => 128,
+        Keycode::Backspace => 129,
+        Keycode::Left => 130,
+        Keycode::Up => 131,
+        Keycode::Right => 132,
+        Keycode::Down => 133,
+        Keycode::Home => 134,
+        Keycode::End => 135,
+        Keycode::PageUp => 136,
+        Keycode::PageDown => 137,
+        Keycode::Insert => 138,
+        Keycode::Delete => 139,
+        Keycode::Escape => 140,
+        Keycode::F1 => 141,
+        Keycode::F2 => 142,
+        Keycode::F3 => 143,
+        Keycode::F4 => 144,
+        Keycode::F5 => 145,
+        Keycode::F6 => 146,
+        Keycode::F7 => 147,
+        Keycode::F8 => 148,
+        Keycode::F9 => 149,
+        Keycode::F10 => 150,
+        Keycode::F11 => 151,
+        Keycode::F12 => 152,
+        Keycode::Kp0 => '0' as u16,
+        Keycode::Kp1 => '1' as u16,
+        Keycode::Kp2 => '2' as u16,
+        Keycode::Kp3 => '3' as u16,
+        Keycode::Kp4 => '4' as u16,
+        Keycode::Kp5 => '5' as u16,
+        Keycode::Kp6 => '6' as u16,
+        Keycode::Kp7 => '7' as u16,
+        Keycode::Kp8 => '8' as u16,
+        Keycode::Kp9 => '9' as u16,
+        Keycode::KpPeriod => '.' as u16,
+        Keycode::KpDivide => '/' as u16,
+        Keycode::KpMultiply => '*' as u16,
+        Keycode::KpMinus => '-' as u16,
+        Keycode::KpPlus => '+' as u16,
+        // SDL keycodes of printable keys are their ASCII characters
+        _ => match keycode as i32 {
+            printable @ 32..=126 => printable as u16,
+            _ => return None,
+        },
+    };
+    Some(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn special_keys() {
+        let mut keyboard = Keyboard::default();
+        assert_eq!(keyboard.code(), 0);
+
+        keyboard.key_down(Keycode::Escape);
+        assert_eq!(keyboard.code(), 140);
+        keyboard.key_down(Keycode::F12);
+        assert_eq!(keyboard.code(), 152);
+        keyboard.key_down(Keycode::Escape);
+        assert_eq!(keyboard.code(), 152);
+
+        keyboard.key_up(Keycode::F12);
+        assert_eq!(keyboard.code(), 140);
+        keyboard.key_up(Keycode::Escape);
+        assert_eq!(keyboard.code(), 0);
+
+        // Modifiers have no code
+        keyboard.key_down(Keycode::LShift);
+        assert_eq!(keyboard.code(), 0);
+    }
+
+    #[test]
+    fn text_input() {
+        let mut keyboard = Keyboard::default();
+        keyboard.key_down(Keycode::A);
+        assert_eq!(keyboard.code(), 'a' as u16);
+        keyboard.text_input("A");
+        assert_eq!(keyboard.code(), 'A' as u16);
+
+        keyboard.key_down(Keycode::Num1);
+        keyboard.text_input("!");
+        assert_eq!(keyboard.code(), '!' as u16);
+        keyboard.key_up(Keycode::Num1);
+        assert_eq!(keyboard.code(), 'A' as u16);
+
+        // Characters outside of ASCII are ignored
+        keyboard.text_input("é");
+        assert_eq!(keyboard.code(), 'A' as u16);
+
+        // Only the key pressed last types text
+        keyboard.key_down(Keycode::LShift);
+        keyboard.text_input("!");
+        assert_eq!(keyboard.code(), 'A' as u16);
+        keyboard.key_down(Keycode::Kp7);
+        assert_eq!(keyboard.code(), '7' as u16);
+
+        keyboard.clear();
+        assert_eq!(keyboard.code(), 0);
+        keyboard.text_input("b");
+        assert_eq!(keyboard.code(), 0);
+    }
+}
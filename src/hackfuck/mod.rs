@@ -1,9 +1,39 @@
+mod cache;
 mod consts;
+mod frontend;
+#[cfg(test)]
+mod fuzz;
+#[cfg(test)]
+mod golden;
+mod idiom;
 mod instruction;
 mod interpreter;
+mod ir;
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+mod jit;
+#[cfg(feature = "sdl")]
+mod keyboard;
 mod parser;
+mod screen;
+mod script;
 mod tape;
+mod terminal;
 mod utils;
+mod wasm;
+#[cfg(feature = "sdl")]
+mod window;
 
-pub use interpreter::Interpreter;
-pub use parser::Parser;
+pub use cache::IrCache;
+pub use frontend::{Frontend, Headless};
+pub use idiom::Idiom;
+pub use instruction::Instruction;
+pub use interpreter::{Exit, Interpreter, Limits, Observer, DEFAULT_FPS};
+pub use ir::{dump_ir, emit_bf, emit_c};
+pub use parser::{Parser, Pass, PassManager, PassStats};
+pub use screen::{Screen, HEIGHT, WIDTH};
+pub use script::{KeyEvent, KeyScript, Time};
+pub use tape::Tape;
+pub use terminal::{Glyphs, Terminal};
+pub use wasm::emit_wat;
+#[cfg(feature = "sdl")]
+pub use window::{Rgb, Window, WindowOptions};
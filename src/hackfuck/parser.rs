@@ -1,12 +1,17 @@
+use super::idiom::Templates;
 use super::instruction::Instruction;
 use anyhow::{Error, Result};
 
 use std::cmp::Ordering;
 use std::collections::{HashMap, VecDeque};
+use std::fmt;
 use std::fs;
 use std::fs::File;
+use std::hash::{Hash, Hasher};
 use std::io::Read;
+use std::mem;
 use std::path::Path;
+use std::str::FromStr;
 
 pub struct Parser {
     instructions: Vec<Instruction>,
@@ -36,12 +41,12 @@ impl Parser {
                 b'-' => parsed_instructions.push(Instruction::DecCell(1, 0)),
                 b'[' => {
                     loop_stack.push_back(parsed_instructions.len());
-                    parsed_instructions.push(Instruction::StartLoop(usize::MAX));
+                    parsed_instructions.push(Instruction::StartLoop(usize::MAX, 0));
                 }
                 b']' => {
                     let start = loop_stack.pop_back().unwrap();
                     loop_map.insert(start, parsed_instructions.len());
-                    parsed_instructions.push(Instruction::EndLoop(usize::MAX));
+                    parsed_instructions.push(Instruction::EndLoop(usize::MAX, 0));
                 }
                 b'#' => parsed_instructions.push(Instruction::BreakPoint),
                 _ => {}
@@ -64,18 +69,44 @@ impl Parser {
         self.instructions
     }
 
-    /// Performs a series of optimizations on the loaded program
+    /// Performs every default optimization on the loaded program, see `PassManager`
     #[allow(dead_code)]
-    pub fn optimized_parse(mut self, debug: bool) -> Vec<Instruction> {
-        self.batch_optimization();
-        self.order_optimization(debug);
-        self.redundancy_optimization();
-        self.batch_optimization();
-        // self.direct_cell_mod_optimization();
-        self.fix_loops().unwrap();
-        // self.bounded_loop_optimization();
-        // self.fix_loops().unwrap();
-        self.instructions
+    pub fn optimized_parse(self, debug: bool) -> Vec<Instruction> {
+        PassManager::new(3, debug).run(self)
+    }
+
+    /// Optimizations idiom templates are generated with
+    pub(super) fn template_parse(self) -> Vec<Instruction> {
+        let mut instructions = PassManager::from_passes(&REWRITE_PASSES, false).run(self);
+        // Inside a program the final pointer movement is merged into the code that follows
+        if instructions.last().is_some_and(Instruction::mem_op) {
+            instructions.pop();
+        }
+        instructions
+    }
+
+    /// Runs a single optimization pass
+    ///
+    /// # Arguments
+    /// * `pass` - Optimization to perform
+    /// * `debug` - Breakpoints are kept if set
+    fn run_pass(&mut self, pass: Pass, debug: bool) {
+        // Idioms and guards count the instructions following them, other passes would break them
+        self.instructions.retain(|instruction| match instruction {
+            Instruction::Idiom(_, _) => pass == Pass::Bounds,
+            Instruction::Guard(_, _, _) => false,
+            _ => true,
+        });
+
+        match pass {
+            Pass::Batch => self.batch_optimization(),
+            Pass::Order => self.order_optimization(debug),
+            Pass::Redundancy => self.redundancy_optimization(),
+            Pass::Scan => self.scan_optimization(),
+            Pass::DirectCellMod => self.direct_cell_mod_optimization(),
+            Pass::Idiom => self.idiom_optimization(),
+            Pass::Bounds => self.bounds_optimization(),
+        }
     }
 
     /// Pairs StartLoop and EndLoop instructions
@@ -84,9 +115,9 @@ impl Parser {
         let mut loop_map = HashMap::new();
 
         for (i, instruction) in self.instructions.iter().enumerate() {
-            if let Instruction::StartLoop(_) = instruction {
+            if let Instruction::StartLoop(_, _) = instruction {
                 loop_stack.push_back(i);
-            } else if let Instruction::EndLoop(_) = instruction {
+            } else if let Instruction::EndLoop(_, _) = instruction {
                 let start_index = loop_stack.pop_back().unwrap();
                 loop_map.insert(start_index, i);
             }
@@ -101,20 +132,25 @@ impl Parser {
 
     /// Combines consecutive instructions of the same type
     fn batch_optimization(&mut self) {
-        let mut prev = Instruction::StartLoop(0);
+        let mut prev = Instruction::StartLoop(0, 0);
         let mut batch = 1;
 
         let mut new_instructions = vec![];
 
         for &instruction in &self.instructions {
-            // Can't do batch optimization on loops and breakpoints
-            if let Instruction::StartLoop(_) | Instruction::EndLoop(_) | Instruction::BreakPoint =
-                instruction
+            // Can't do batch optimization on loops, scans, idioms, guards and breakpoints
+            if let Instruction::StartLoop(_, _)
+            | Instruction::EndLoop(_, _)
+            | Instruction::Scan(_)
+            | Instruction::Idiom(_, _)
+            | Instruction::Guard(_, _, _)
+            | Instruction::BreakPoint = instruction
             {
                 new_instructions.push(instruction);
                 prev = instruction;
             } else if instruction == prev {
-                batch += 1;
+                // Earlier passes may have batched the instruction already
+                batch += instruction.batch().unwrap();
                 new_instructions
                     .last_mut()
                     .unwrap()
@@ -123,12 +159,142 @@ impl Parser {
             } else {
                 new_instructions.push(instruction);
                 prev = instruction;
-                batch = 1;
+                batch = instruction.batch().unwrap();
             }
         }
         self.instructions = new_instructions;
     }
 
+    /// Replaces loops that only move the pointer with a single scan for a zero cell
+    fn scan_optimization(&mut self) {
+        // Memory traversal loops such as [>>>] or [<<<] become Scan(3) or Scan(-3)
+        let mut new_instructions = vec![];
+
+        let mut i = 0;
+        while i < self.instructions.len() {
+            let window = &self.instructions[i..self.instructions.len().min(i + 3)];
+
+            let stride = match window {
+                [Instruction::StartLoop(_, 0), Instruction::IncPtr(batch), Instruction::EndLoop(_, 0)] => {
+                    Some(*batch as isize)
+                }
+                [Instruction::StartLoop(_, 0), Instruction::DecPtr(batch), Instruction::EndLoop(_, 0)] => {
+                    Some(-(*batch as isize))
+                }
+                _ => None,
+            };
+
+            match stride {
+                Some(stride) => {
+                    new_instructions.push(Instruction::Scan(stride));
+                    i += 3;
+                }
+                None => {
+                    new_instructions.push(self.instructions[i]);
+                    i += 1;
+                }
+            }
+        }
+
+        self.instructions = new_instructions
+    }
+
+    /// Marks code generated by brainhack macros with the high-level operation it performs
+    fn idiom_optimization(&mut self) {
+        let mut templates = Templates::default();
+        let mut new_instructions = vec![];
+
+        let mut i = 0;
+        while i < self.instructions.len() {
+            match templates.recognize(&self.instructions[i..]) {
+                Some((idiom, len)) => {
+                    // The original code is kept in case the idiom's preconditions don't hold
+                    new_instructions.push(Instruction::Idiom(idiom, len));
+                    new_instructions.extend_from_slice(&self.instructions[i..i + len]);
+                    i += len;
+                }
+                None => {
+                    new_instructions.push(self.instructions[i]);
+                    i += 1;
+                }
+            }
+        }
+
+        self.instructions = new_instructions
+    }
+
+    /// Guards regions where the memory pointer doesn't move with a single bounds check
+    fn bounds_optimization(&mut self) {
+        let mut new_instructions = vec![];
+
+        let mut i = 0;
+        while i < self.instructions.len() {
+            // Code recognized as an idiom is kept as is, its length must not change
+            if let Instruction::Idiom(_, len) = self.instructions[i] {
+                new_instructions.extend_from_slice(&self.instructions[i..=i + len]);
+                i += len + 1;
+                continue;
+            }
+
+            match self.guarded_region(i) {
+                // A single instruction is checked just as often without a guard
+                Some((len, min_offset, max_offset)) if len > 1 => {
+                    new_instructions.push(Instruction::Guard(len, min_offset, max_offset));
+                    new_instructions.extend_from_slice(&self.instructions[i..i + len]);
+                    i += len;
+                }
+                _ => {
+                    new_instructions.push(self.instructions[i]);
+                    i += 1;
+                }
+            }
+        }
+
+        self.instructions = new_instructions
+    }
+
+    /// Finds the region of cell operations and balanced loops starting at an instruction
+    ///
+    /// # Arguments
+    /// * `start` - Index of the first instruction in the region
+    ///
+    /// # Returns
+    /// Returns the region's length and the lowest and highest offset it accesses,
+    /// None if the region is empty
+    fn guarded_region(&self, start: usize) -> Option<(usize, isize, isize)> {
+        let mut region = None;
+        // Offsets accessed so far, may include an unfinished loop
+        let (mut min_offset, mut max_offset) = (isize::MAX, isize::MIN);
+        let mut depth = 0;
+
+        for (i, instruction) in self.instructions.iter().enumerate().skip(start) {
+            let offset = match instruction {
+                Instruction::IncCell(_, offset) | Instruction::DecCell(_, offset) => *offset,
+                Instruction::StartLoop(_, offset) => {
+                    depth += 1;
+                    *offset
+                }
+                // Loops that enclose the region can't be part of it
+                Instruction::EndLoop(_, _) if depth == 0 => break,
+                Instruction::EndLoop(_, offset) => {
+                    depth -= 1;
+                    *offset
+                }
+                // Anything else moves the memory pointer or has to run checked
+                _ => break,
+            };
+            min_offset = min_offset.min(offset);
+            max_offset = max_offset.max(offset);
+
+            // Loops are only part of the region once the pointer is known to stay put in them
+            if depth == 0 {
+                region = Some((i + 1 - start, min_offset, max_offset));
+            }
+        }
+
+        region
+    }
+
     /// Combines consecutive instructions of contradictory purpose
     fn redundancy_optimization(&mut self) {
         // Goes through and checks if consecutive instructions contradict, can be done multiple times
@@ -137,8 +303,8 @@ impl Parser {
         // They don't have the same batch size
         // and have the same offset
         // append an instruction with the one with the larger batch subtracted from the other one
-        // If they are both cell ops, since this step follows batch optimization
-        // inst1 cannot be the same type as inst2
+        // Instructions of the same type are left for batch optimization, the order pass
+        // can make them consecutive
         let mut new_instructions = vec![];
 
         let mut i = 0;
@@ -152,7 +318,17 @@ impl Parser {
                 }
             };
 
-            if inst1.cell_op() && inst2.cell_op() {
+            let opposite = matches!(
+                (inst1, inst2),
+                (Instruction::IncCell(_, _), Instruction::DecCell(_, _))
+                    | (Instruction::DecCell(_, _), Instruction::IncCell(_, _))
+                    | (Instruction::IncPtr(_), Instruction::DecPtr(_))
+                    | (Instruction::DecPtr(_), Instruction::IncPtr(_))
+            );
+
+            if !opposite {
+                new_instructions.push(inst1);
+            } else if inst1.cell_op() && inst2.cell_op() {
                 let (batch1, offset1) = if let Instruction::IncCell(batch, offset)
                 | Instruction::DecCell(batch, offset) = inst1
                 {
@@ -230,50 +406,115 @@ impl Parser {
 
     /// Order instructions to decrease the number of times the pointer is moved
     fn order_optimization(&mut self, debug: bool) {
-        // SHOULD BE PERFORMED BEFORE ANY OTHER OFFSETS ARE CREATED
-
         // change execution order to increase batching
         let mut offset = 0;
         // Every time a memory operation is encountered this will be adjusted
         // instead of actually doing the mem operation to improve runtime performance
         let mut new_instructions = vec![];
 
-        for instruction in &self.instructions {
+        // Balanced loops keep the offset, their conditions test the cell relative to the pointer
+        let balanced = self.balanced_loops(debug);
+        let mut loop_stack = vec![];
+
+        for (i, instruction) in self.instructions.iter().enumerate() {
             match instruction {
                 Instruction::IncPtr(batch) => offset += *batch as isize,
                 Instruction::DecPtr(batch) => offset -= *batch as isize,
-                Instruction::IncCell(batch, _) => {
-                    new_instructions.push(Instruction::IncCell(*batch, offset))
+                // Offsets left by earlier passes are relative to the moved pointer
+                Instruction::IncCell(batch, cell) => {
+                    new_instructions.push(Instruction::IncCell(*batch, offset + cell))
+                }
+                Instruction::DecCell(batch, cell) => {
+                    new_instructions.push(Instruction::DecCell(*batch, offset + cell))
                 }
-                Instruction::DecCell(batch, _) => {
-                    new_instructions.push(Instruction::DecCell(*batch, offset))
+                Instruction::StartLoop(_, cell) => {
+                    if !balanced[i] {
+                        flush_offset(&mut new_instructions, &mut offset);
+                    }
+                    new_instructions.push(Instruction::StartLoop(usize::MAX, offset + cell));
+                    loop_stack.push(balanced[i]);
                 }
-                Instruction::StartLoop(_) | Instruction::EndLoop(_) => {
-                    match offset.cmp(&0) {
-                        Ordering::Greater => new_instructions.push(Instruction::IncPtr(offset.unsigned_abs())),
-                        Ordering::Less => new_instructions.push(Instruction::DecPtr(offset.unsigned_abs())),
-                        Ordering::Equal => {}
+                Instruction::EndLoop(_, cell) => {
+                    // The body of a balanced loop always returns to the offset it started with
+                    if !loop_stack.pop().unwrap() {
+                        flush_offset(&mut new_instructions, &mut offset);
                     }
+                    new_instructions.push(Instruction::EndLoop(usize::MAX, offset + cell));
+                }
+                Instruction::Scan(_) => {
+                    flush_offset(&mut new_instructions, &mut offset);
                     new_instructions.push(*instruction);
-                    offset = 0;
                 }
                 Instruction::BreakPoint => {
                     if debug {
-                        match offset.cmp(&0) {
-                            Ordering::Greater => new_instructions.push(Instruction::IncPtr(offset.unsigned_abs())),
-                            Ordering::Less => new_instructions.push(Instruction::DecPtr(offset.unsigned_abs())),
-                            Ordering::Equal => {}
-                        }
+                        flush_offset(&mut new_instructions, &mut offset);
                         new_instructions.push(*instruction);
-                        offset = 0;
                     }
                 }
+                // Offsets are about to change, idioms and guards have to be computed again
+                Instruction::Idiom(_, _) | Instruction::Guard(_, _, _) => {}
             }
         }
 
+        // The program has to end where it would have without the optimization
+        flush_offset(&mut new_instructions, &mut offset);
         self.instructions = new_instructions;
     }
 
+    /// Finds loops whose body always leaves the memory pointer where it started
+    ///
+    /// # Arguments
+    /// * `debug` - Breakpoints are kept, so loops containing them must move the pointer
+    ///
+    /// # Returns
+    /// Returns a flag for every instruction, set on the StartLoop of each balanced loop
+    fn balanced_loops(&self, debug: bool) -> Vec<bool> {
+        let mut balanced = vec![false; self.instructions.len()];
+        // (Index of StartLoop, net pointer movement, whether the loop can still be balanced)
+        let mut loop_stack: Vec<(usize, isize, bool)> = vec![];
+
+        for (i, instruction) in self.instructions.iter().enumerate() {
+            match instruction {
+                Instruction::IncPtr(batch) => {
+                    if let Some((_, movement, _)) = loop_stack.last_mut() {
+                        *movement += *batch as isize;
+                    }
+                }
+                Instruction::DecPtr(batch) => {
+                    if let Some((_, movement, _)) = loop_stack.last_mut() {
+                        *movement -= *batch as isize;
+                    }
+                }
+                Instruction::Scan(_) => {
+                    if let Some((_, _, candidate)) = loop_stack.last_mut() {
+                        *candidate = false;
+                    }
+                }
+                Instruction::BreakPoint => {
+                    if let Some((_, _, candidate)) = loop_stack.last_mut() {
+                        *candidate &= !debug;
+                    }
+                }
+                Instruction::StartLoop(_, _) => loop_stack.push((i, 0, true)),
+                Instruction::EndLoop(_, _) => {
+                    let (start, movement, candidate) = loop_stack.pop().unwrap();
+                    balanced[start] = candidate && movement == 0;
+
+                    // A loop is only balanced if every nested loop is
+                    if let Some((_, _, candidate)) = loop_stack.last_mut() {
+                        *candidate &= balanced[start];
+                    }
+                }
+                Instruction::IncCell(_, _)
+                | Instruction::DecCell(_, _)
+                | Instruction::Idiom(_, _)
+                | Instruction::Guard(_, _, _) => {}
+            }
+        }
+
+        balanced
+    }
+
     /// Predecessor to order optimization
     #[allow(dead_code)]
     fn direct_cell_mod_optimization(&mut self) {
@@ -300,7 +541,9 @@ impl Parser {
 
             match (inst1, inst2.cell_op(), inst3) {
                 (Instruction::IncPtr(bl), true, Instruction::DecPtr(br)) => {
-                    inst2.update_offset(bl as isize).unwrap();
+                    inst2
+                        .update_offset(inst2.offset().unwrap() + bl as isize)
+                        .unwrap();
                     new_instructions.push(inst2);
                     match bl.cmp(&br) {
                         Ordering::Greater => new_instructions.push(Instruction::IncPtr(bl - br)),
@@ -309,7 +552,9 @@ impl Parser {
                     }
                 }
                 (Instruction::DecPtr(bl), true, Instruction::IncPtr(br)) => {
-                    inst2.update_offset(-(bl as isize)).unwrap();
+                    inst2
+                        .update_offset(inst2.offset().unwrap() - bl as isize)
+                        .unwrap();
                     new_instructions.push(inst2);
                     match bl.cmp(&br) {
                         Ordering::Greater => new_instructions.push(Instruction::DecPtr(bl - br)),
@@ -338,3 +583,241 @@ impl Parser {
         todo!()
     }
 }
+
+/// Moves the memory pointer by the pending offset
+fn flush_offset(instructions: &mut Vec<Instruction>, offset: &mut isize) {
+    match (*offset).cmp(&0) {
+        Ordering::Greater => instructions.push(Instruction::IncPtr(offset.unsigned_abs())),
+        Ordering::Less => instructions.push(Instruction::DecPtr(offset.unsigned_abs())),
+        Ordering::Equal => {}
+    }
+    *offset = 0;
+}
+
+/// Optimization passes that can be performed on a loaded program
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Pass {
+    /// Combines consecutive instructions of the same type
+    Batch,
+    /// Replaces pointer movement with cell offsets
+    Order,
+    /// Combines consecutive instructions of contradictory purpose
+    Redundancy,
+    /// Replaces loops that only move the pointer with a scan
+    Scan,
+    /// Predecessor to the order pass, offsets cells between opposite pointer moves
+    DirectCellMod,
+    /// Recognizes code generated by brainhack macros
+    Idiom,
+    /// Checks the bounds of regions where the pointer doesn't move once
+    Bounds,
+}
+
+impl Pass {
+    /// Every pass, in the order they are accepted by `FromStr`
+    pub const ALL: [Pass; 7] = [
+        Pass::Batch,
+        Pass::Order,
+        Pass::Redundancy,
+        Pass::Scan,
+        Pass::DirectCellMod,
+        Pass::Idiom,
+        Pass::Bounds,
+    ];
+
+    /// Name of the pass on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            Pass::Batch => "batch",
+            Pass::Order => "order",
+            Pass::Redundancy => "redundancy",
+            Pass::Scan => "scan",
+            Pass::DirectCellMod => "direct-cell-mod",
+            Pass::Idiom => "idiom",
+            Pass::Bounds => "bounds",
+        }
+    }
+}
+
+impl fmt::Display for Pass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+impl FromStr for Pass {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        Pass::ALL
+            .into_iter()
+            .find(|pass| pass.name() == name)
+            .ok_or_else(|| {
+                let names: Vec<_> = Pass::ALL.iter().map(Pass::name).collect();
+                Error::msg(format!(
+                    "Unknown pass `{name}`, expected one of: {}",
+                    names.join(", ")
+                ))
+            })
+    }
+}
+
+/// Passes that rewrite the program, idiom templates are generated with them
+const REWRITE_PASSES: [Pass; 5] = [
+    Pass::Batch,
+    Pass::Order,
+    Pass::Redundancy,
+    Pass::Batch,
+    Pass::Scan,
+];
+
+/// Number of instructions changed by a pass
+#[derive(Debug, Copy, Clone)]
+pub struct PassStats {
+    pub pass: Pass,
+    pub before: usize,    // Number of instructions the pass received
+    pub after: usize,     // Number of instructions the pass returned
+    pub removed: usize,   // Received instructions that weren't returned unchanged
+    pub rewritten: usize, // Returned instructions that weren't received
+}
+
+impl fmt::Display for PassStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:<16}{:>10} -> {:>10} instructions, {} removed, {} rewritten",
+            self.pass, self.before, self.after, self.removed, self.rewritten
+        )
+    }
+}
+
+/// Ordered list of optimization passes to perform on a loaded program
+#[derive(Debug, Clone)]
+pub struct PassManager {
+    passes: Vec<Pass>,
+    debug: bool,
+}
+
+impl PassManager {
+    /// Creates a pass manager with the default passes of an optimization level
+    ///
+    /// # Arguments
+    /// * `opt_level` - 0 performs no passes, 3 and above perform every default pass
+    /// * `debug` - Breakpoints are kept if set
+    pub fn new(opt_level: u8, debug: bool) -> Self {
+        let passes = match opt_level {
+            0 => vec![],
+            1 => REWRITE_PASSES[..4].to_vec(),
+            2 => REWRITE_PASSES.to_vec(),
+            _ => [&REWRITE_PASSES[..], &[Pass::Idiom, Pass::Bounds]].concat(),
+        };
+        Self { passes, debug }
+    }
+
+    /// Creates a pass manager performing passes in the given order
+    pub fn from_passes(passes: &[Pass], debug: bool) -> Self {
+        Self {
+            passes: passes.to_vec(),
+            debug,
+        }
+    }
+
+    /// Passes performed, in order
+    pub fn passes(&self) -> &[Pass] {
+        &self.passes
+    }
+
+    /// Whether breakpoints are kept
+    pub fn debug(&self) -> bool {
+        self.debug
+    }
+
+    /// Appends a pass unless it is already performed
+    pub fn enable(&mut self, pass: Pass) -> &mut Self {
+        if !self.passes.contains(&pass) {
+            self.passes.push(pass);
+        }
+        self
+    }
+
+    /// Stops performing a pass
+    pub fn disable(&mut self, pass: Pass) -> &mut Self {
+        self.passes.retain(|&enabled| enabled != pass);
+        self
+    }
+
+    /// Performs every pass on the loaded program
+    pub fn run(&self, mut parser: Parser) -> Vec<Instruction> {
+        for &pass in &self.passes {
+            parser.run_pass(pass, self.debug);
+        }
+        parser.parse()
+    }
+
+    /// Performs every pass on the loaded program, counting the instructions each pass changed
+    pub fn run_with_stats(&self, mut parser: Parser) -> (Vec<Instruction>, Vec<PassStats>) {
+        let mut stats = vec![];
+
+        for &pass in &self.passes {
+            // Instructions received by the pass, counted by shape
+            let mut received: HashMap<Shape, usize> = HashMap::new();
+            for &instruction in &parser.instructions {
+                *received.entry(Shape(instruction)).or_default() += 1;
+            }
+            let before = parser.instructions.len();
+
+            parser.run_pass(pass, self.debug);
+
+            let mut rewritten = 0;
+            for &instruction in &parser.instructions {
+                match received.get_mut(&Shape(instruction)) {
+                    Some(count) if *count > 0 => *count -= 1,
+                    _ => rewritten += 1,
+                }
+            }
+            let after = parser.instructions.len();
+
+            stats.push(PassStats {
+                pass,
+                before,
+                after,
+                removed: before + rewritten - after,
+                rewritten,
+            });
+        }
+
+        (parser.parse(), stats)
+    }
+}
+
+/// Instruction compared with `Instruction::same_shape`, so it can be counted
+struct Shape(Instruction);
+
+impl PartialEq for Shape {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.same_shape(&other.0)
+    }
+}
+
+impl Eq for Shape {}
+
+impl Hash for Shape {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(&self.0).hash(state);
+        match self.0 {
+            Instruction::IncPtr(batch) | Instruction::DecPtr(batch) => batch.hash(state),
+            Instruction::IncCell(batch, offset) | Instruction::DecCell(batch, offset) => {
+                (batch, offset).hash(state)
+            }
+            // Loop indices aren't part of the shape
+            Instruction::StartLoop(_, offset)
+            | Instruction::EndLoop(_, offset)
+            | Instruction::Scan(offset) => offset.hash(state),
+            Instruction::Idiom(idiom, len) => (idiom, len).hash(state),
+            Instruction::Guard(len, min_offset, max_offset) => {
+                (len, min_offset, max_offset).hash(state)
+            }
+            Instruction::BreakPoint => {}
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use super::consts::SCREEN;
+
+use anyhow::Result;
+
+/// Width of the Hack screen in pixels
+pub const WIDTH: usize = 512;
+
+/// Height of the Hack screen in pixels
+pub const HEIGHT: usize = 256;
+
+/// Read-only view of the Hack screen stored in a tape
+///
+/// Each word holds 16 pixels, like on the Hack computer pixel `x` is bit `x % 16` of its word
+/// counted from the least significant bit, so the leftmost pixel is the lowest bit of the lower cell
+#[derive(Debug, Clone, Copy)]
+pub struct Screen<'a> {
+    cells: &'a [u8],
+}
+
+impl<'a> Screen<'a> {
+    /// Creates a view over the cells of the screen, the gap after each word is ignored
+    pub(crate) fn new(cells: &'a [u8]) -> Self {
+        assert_eq!(cells.len(), SCREEN, "the screen holds 8192 words");
+        Self { cells }
+    }
+
+    /// Returns whether a pixel is black
+    ///
+    /// # Arguments
+    /// * `x` - Column of the pixel, from the left
+    /// * `y` - Row of the pixel, from the top
+    ///
+    /// # Panics
+    /// Panics if the pixel is outside of the screen
+    pub fn get_pixel(&self, x: usize, y: usize) -> bool {
+        assert!(
+            x < WIDTH && y < HEIGHT,
+            "pixel ({x}, {y}) is outside of the screen"
+        );
+        let word = y * WIDTH / 16 + x / 16;
+        let value = u16::from_be_bytes([self.cells[3 * word], self.cells[3 * word + 1]]);
+        (value >> (x % 16)) & 1 != 0
+    }
+
+    /// Returns the rows of the screen, 8 pixels per byte with the leftmost one in the highest bit
+    /// and black pixels set
+    fn rows(&self) -> impl Iterator<Item = Vec<u8>> + 'a {
+        // The lower cell holds the left half of a word, from its lowest bit
+        self.cells.chunks(3 * WIDTH / 16).map(|row| {
+            row.chunks(3)
+                .flat_map(|word| [word[1].reverse_bits(), word[0].reverse_bits()])
+                .collect()
+        })
+    }
+
+    /// Encodes the screen as a binary PBM image
+    pub fn to_pbm(&self) -> Vec<u8> {
+        let mut image = format!("P4\n{WIDTH} {HEIGHT}\n").into_bytes();
+        for row in self.rows() {
+            image.extend(row);
+        }
+        image
+    }
+
+    /// Draws the screen as text, one line per row with `#` for black pixels and `.` for white ones
+    pub fn to_ascii(&self) -> String {
+        let mut text = String::with_capacity((WIDTH + 1) * HEIGHT);
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                text.push(if self.get_pixel(x, y) { '#' } else { '.' });
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Encodes the screen as a black and white PNG image
+    pub fn to_png(&self) -> Result<Vec<u8>> {
+        let mut image = vec![];
+        let mut encoder = png::Encoder::new(&mut image, WIDTH as u32, HEIGHT as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::One);
+
+        // Grayscale images store white as one, unlike the screen
+        let data: Vec<u8> = self.rows().flatten().map(|byte| !byte).collect();
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&data)?;
+        writer.finish()?;
+        Ok(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Screen with the leftmost pixel of the top row and the last pixel of the second row set,
+    /// which are bit 0 of the first word and bit 15 of the last word of the row
+    fn cells() -> Vec<u8> {
+        let mut cells = vec![0; SCREEN];
+        cells[1] = 0x01;
+        cells[3 * 63] = 0x80;
+        cells
+    }
+
+    #[test]
+    fn get_pixel() {
+        let cells = cells();
+        let screen = Screen::new(&cells);
+        assert!(screen.get_pixel(0, 0));
+        assert!(!screen.get_pixel(1, 0));
+        assert!(!screen.get_pixel(15, 0));
+        assert!(screen.get_pixel(WIDTH - 1, 1));
+        assert!(!screen.get_pixel(WIDTH - 16, 1));
+        assert!(!screen.get_pixel(WIDTH - 1, 0));
+
+        // Pixel x is bit x of the word, the upper cell holds the right half
+        let mut cells = vec![0; SCREEN];
+        cells[..2].copy_from_slice(&0b0000_0001_0000_0110u16.to_be_bytes());
+        let screen = Screen::new(&cells);
+        let pixels: Vec<_> = (0..16).filter(|&x| screen.get_pixel(x, 0)).collect();
+        assert_eq!(pixels, [1, 2, 8]);
+    }
+
+    #[test]
+    fn to_pbm() {
+        let cells = cells();
+        let image = Screen::new(&cells).to_pbm();
+        assert_eq!(&image[..13], b"P4\n512 256\n\x80\x00");
+        assert_eq!(image.len(), 11 + WIDTH / 8 * HEIGHT);
+        assert_eq!(image[11 + 2 * WIDTH / 8 - 1], 0x01);
+    }
+
+    #[test]
+    fn to_ascii() {
+        let cells = cells();
+        let text = Screen::new(&cells).to_ascii();
+        let rows: Vec<_> = text.lines().collect();
+        assert_eq!(rows.len(), HEIGHT);
+        assert_eq!(&rows[0][..3], "#..");
+        assert!(rows[1].ends_with(".#"));
+        assert_eq!(text.matches('#').count(), 2);
+    }
+
+    #[test]
+    fn to_png() -> Result<()> {
+        let cells = cells();
+        let image = Screen::new(&cells).to_png()?;
+
+        let mut reader = png::Decoder::new(image.as_slice()).read_info()?;
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data)?;
+        assert_eq!((info.width, info.height), (WIDTH as u32, HEIGHT as u32));
+        assert_eq!(info.bit_depth, png::BitDepth::One);
+        assert_eq!(&data[..2], &[0x7f, 0xff]);
+        assert_eq!(data[2 * WIDTH / 8 - 1], 0xfe);
+        Ok(())
+    }
+}
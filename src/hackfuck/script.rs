@@ -0,0 +1,400 @@
+use super::tape::Tape;
+
+use anyhow::{bail, Context, Result};
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+
+/// Names of the keys without a printable character, with their Hack codes
+static KEY_NAMES: &[(&str, u16)] = &[
+    ("space", 32),
+    ("enter", 128),
+    ("backspace", 129),
+    ("left", 130),
+    ("up", 131),
+    ("right", 132),
+    ("down", 133),
+    ("home", 134),
+    ("end", 135),
+    ("pageup", 136),
+    ("pagedown", 137),
+    ("insert", 138),
+    ("delete", 139),
+    ("escape", 140),
+    ("f1", 141),
+    ("f2", 142),
+    ("f3", 143),
+    ("f4", 144),
+    ("f5", 145),
+    ("f6", 146),
+    ("f7", 147),
+    ("f8", 148),
+    ("f9", 149),
+    ("f10", 150),
+    ("f11", 151),
+    ("f12", 152),
+];
+
+/// When a scripted key is pressed or released
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Time {
+    /// Once the program executed this many instructions
+    Step(usize),
+    /// Once this many frames were drawn, only available with a window
+    Frame(usize),
+}
+
+/// Change of the keyboard in a key script
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub time: Time,
+    /// Whether the key is pressed or released
+    pub pressed: bool,
+    /// Hack code of the key
+    pub code: u16,
+}
+
+impl fmt::Display for KeyEvent {
+    /// Writes the event as a line of a key script, without the line break
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.time {
+            Time::Step(step) => write!(f, "{step}")?,
+            Time::Frame(frame) => write!(f, "frame {frame}")?,
+        }
+        let action = if self.pressed { "press" } else { "release" };
+        match KEY_NAMES.iter().find(|&&(_, code)| code == self.code) {
+            Some((name, _)) => write!(f, " {action} {name}"),
+            None if (33..=126).contains(&self.code) => {
+                write!(f, " {action} {}", self.code as u8 as char)
+            }
+            // A single digit would be read back as a character
+            None => write!(f, " {action} {:02}", self.code),
+        }
+    }
+}
+
+impl FromStr for KeyEvent {
+    type Err = anyhow::Error;
+
+    /// Parses a line like `1000 press a` or `frame 60 release left`
+    fn from_str(line: &str) -> Result<Self> {
+        let mut words = line.split_whitespace();
+        let mut word = || {
+            words
+                .next()
+                .context("expected `<step> press|release <key>`")
+        };
+
+        let time = match word()? {
+            "frame" => Time::Frame(word()?.parse().context("invalid frame number")?),
+            step => Time::Step(step.parse().context("invalid step count")?),
+        };
+        let pressed = match word()? {
+            "press" => true,
+            "release" => false,
+            action => bail!("unknown action '{action}', expected press or release"),
+        };
+        let code = parse_key(word()?)?;
+        if let Some(extra) = words.next() {
+            bail!("unexpected '{extra}' after the key");
+        }
+        Ok(Self {
+            time,
+            pressed,
+            code,
+        })
+    }
+}
+
+/// Parses a key given as a printable character, a name like `left`, or a code of several digits
+fn parse_key(key: &str) -> Result<u16> {
+    let mut chars = key.chars();
+    if let (Some(character), None) = (chars.next(), chars.next()) {
+        if (' '..='~').contains(&character) {
+            return Ok(character as u16);
+        }
+    }
+    if let Some(&(_, code)) = KEY_NAMES
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(key))
+    {
+        return Ok(code);
+    }
+    key.parse().with_context(|| format!("unknown key '{key}'"))
+}
+
+/// Key presses and releases applied while a program runs
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyScript {
+    events: Vec<KeyEvent>,
+}
+
+impl KeyScript {
+    /// Parses a key script, one event per line, lines starting with `#` are comments
+    ///
+    /// # Returns
+    /// Returns Err with the line number if a line is invalid
+    pub fn parse(script: &str) -> Result<Self> {
+        let mut events = vec![];
+        for (line_number, line) in script.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let event = line
+                .parse()
+                .with_context(|| format!("line {}: invalid key event '{line}'", line_number + 1))?;
+            events.push(event);
+        }
+        Ok(Self { events })
+    }
+
+    /// Returns the events in the order they are applied
+    pub fn events(&self) -> &[KeyEvent] {
+        &self.events
+    }
+
+    /// Whether some events are timed in frames
+    pub fn uses_frames(&self) -> bool {
+        self.events
+            .iter()
+            .any(|event| matches!(event.time, Time::Frame(_)))
+    }
+
+    /// Whether some events are timed in executed instructions
+    pub fn uses_steps(&self) -> bool {
+        self.events
+            .iter()
+            .any(|event| matches!(event.time, Time::Step(_)))
+    }
+}
+
+impl fmt::Display for KeyScript {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for event in &self.events {
+            writeln!(f, "{event}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Plays a key script back, KBD reads the scripted key held down last
+#[derive(Debug, Default)]
+pub(super) struct Player {
+    /// Events timed in steps and in frames, each sorted by time
+    steps: VecDeque<(usize, bool, u16)>,
+    frames: VecDeque<(usize, bool, u16)>,
+    /// Codes of the held keys, in the order they were pressed
+    held: Vec<u16>,
+}
+
+impl Player {
+    pub(super) fn new(script: &KeyScript) -> Self {
+        let (mut steps, mut frames) = (vec![], vec![]);
+        for event in script.events() {
+            match event.time {
+                Time::Step(step) => steps.push((step, event.pressed, event.code)),
+                Time::Frame(frame) => frames.push((frame, event.pressed, event.code)),
+            }
+        }
+        // Events at the same time keep their order
+        steps.sort_by_key(|&(step, _, _)| step);
+        frames.sort_by_key(|&(frame, _, _)| frame);
+        Self {
+            steps: steps.into(),
+            frames: frames.into(),
+            held: vec![],
+        }
+    }
+
+    pub(super) fn uses_steps(&self) -> bool {
+        !self.steps.is_empty()
+    }
+
+    pub(super) fn uses_frames(&self) -> bool {
+        !self.frames.is_empty()
+    }
+
+    /// Returns the number of executed instructions at the next event, usize::MAX without one
+    pub(super) fn next_step(&self) -> usize {
+        self.steps.front().map_or(usize::MAX, |&(step, _, _)| step)
+    }
+
+    /// Applies the events due after a number of executed instructions
+    ///
+    /// # Returns
+    /// Returns the number of executed instructions at the next event
+    pub(super) fn play_steps(&mut self, steps: usize, tape: &mut Tape) -> usize {
+        while let Some(&(step, pressed, code)) = self.steps.front() {
+            if step > steps {
+                break;
+            }
+            self.steps.pop_front();
+            self.apply(pressed, code, tape);
+        }
+        self.next_step()
+    }
+
+    /// Applies the events due once a number of frames were drawn
+    pub(super) fn play_frames(&mut self, frames: usize, tape: &mut Tape) {
+        while let Some(&(frame, pressed, code)) = self.frames.front() {
+            if frame > frames {
+                break;
+            }
+            self.frames.pop_front();
+            self.apply(pressed, code, tape);
+        }
+    }
+
+    fn apply(&mut self, pressed: bool, code: u16, tape: &mut Tape) {
+        self.held.retain(|&held| held != code);
+        if pressed {
+            self.held.push(code);
+        }
+        tape.set_kbd(self.held.last().copied().unwrap_or(0));
+    }
+}
+
+/// Writes every change of KBD as key script events timed in steps
+///
+/// A change releases the previous key and presses the new one, so replaying the script sets KBD
+/// to the same values at the same steps
+#[derive(Debug, Default)]
+pub(super) struct Recorder {
+    script: KeyScript,
+    /// Value of KBD after the last recorded event
+    kbd: u16,
+}
+
+impl Recorder {
+    /// Records a change of KBD, if there is one
+    ///
+    /// # Arguments
+    /// * `steps` - Number of executed instructions, the next one is the first to see the change
+    /// * `tape` - Tape holding KBD
+    pub(super) fn record(&mut self, steps: usize, tape: &Tape) {
+        let kbd = tape.kbd();
+        if kbd == self.kbd {
+            return;
+        }
+        let time = Time::Step(steps);
+        if self.kbd != 0 {
+            self.script.events.push(KeyEvent {
+                time,
+                pressed: false,
+                code: self.kbd,
+            });
+        }
+        if kbd != 0 {
+            self.script.events.push(KeyEvent {
+                time,
+                pressed: true,
+                code: kbd,
+            });
+        }
+        self.kbd = kbd;
+    }
+
+    /// Returns the events recorded so far
+    pub(super) fn script(&self) -> &KeyScript {
+        &self.script
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() -> Result<()> {
+        let script = KeyScript::parse(
+            "# Starts the game\n\
+             0 press a\n\
+             \n\
+             frame 60 release A\n\
+             1000 press LEFT\n\
+             1000 release 132\n\
+             20 press #\n",
+        )?;
+        let codes: Vec<_> = script.events().iter().map(|event| event.code).collect();
+        assert_eq!(codes, [97, 65, 130, 132, 35]);
+        assert_eq!(script.events()[1].time, Time::Frame(60));
+        assert!(!script.events()[3].pressed);
+        assert!(script.uses_frames() && script.uses_steps());
+
+        // Digits are characters, codes have several digits
+        assert_eq!(parse_key("1")?, '1' as u16);
+        assert_eq!(parse_key("49")?, '1' as u16);
+
+        assert!(KeyScript::parse("10 press").is_err());
+        assert!(KeyScript::parse("10 hold a").is_err());
+        assert!(KeyScript::parse("ten press a").is_err());
+        assert!(KeyScript::parse("10 press shift").is_err());
+        assert!(KeyScript::parse("10 press a b").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn display() -> Result<()> {
+        let text = "0 press a\nframe 60 release left\n10 press 200\n20 press space\n30 press 05\n";
+        assert_eq!(KeyScript::parse(text)?.to_string(), text);
+        assert_eq!(KeyScript::parse(text)?.events()[4].code, 5);
+        Ok(())
+    }
+
+    #[test]
+    fn play() -> Result<()> {
+        let script = KeyScript::parse("10 press a\n5 press b\n20 release b\nframe 2 release a")?;
+        let mut player = Player::new(&script);
+        let mut tape = Tape::new();
+
+        assert_eq!(player.next_step(), 5);
+        assert_eq!(player.play_steps(4, &mut tape), 5);
+        assert_eq!(tape.kbd(), 0);
+        assert_eq!(player.play_steps(5, &mut tape), 10);
+        assert_eq!(tape.kbd(), 'b' as u16);
+        assert_eq!(player.play_steps(15, &mut tape), 20);
+        assert_eq!(tape.kbd(), 'a' as u16);
+        assert_eq!(player.play_steps(20, &mut tape), usize::MAX);
+        assert_eq!(tape.kbd(), 'a' as u16);
+
+        player.play_frames(1, &mut tape);
+        assert_eq!(tape.kbd(), 'a' as u16);
+        player.play_frames(2, &mut tape);
+        assert_eq!(tape.kbd(), 0);
+
+        // Events at the same step are applied in the order of the script
+        let script = KeyScript::parse("10 press a\n10 release a\n10 press b")?;
+        let mut player = Player::new(&script);
+        player.play_steps(10, &mut tape);
+        assert_eq!(tape.kbd(), 'b' as u16);
+        Ok(())
+    }
+
+    #[test]
+    fn record() -> Result<()> {
+        let mut recorder = Recorder::default();
+        let mut tape = Tape::new();
+        recorder.record(0, &tape);
+        tape.set_kbd('a' as u16);
+        recorder.record(10, &tape);
+        recorder.record(20, &tape);
+        tape.set_kbd(130);
+        recorder.record(30, &tape);
+        tape.set_kbd(0);
+        recorder.record(40, &tape);
+        assert_eq!(
+            recorder.script().to_string(),
+            "10 press a\n30 release a\n30 press left\n40 release left\n"
+        );
+
+        // Replaying the recording sets KBD to the same values
+        let mut player = Player::new(recorder.script());
+        let mut replayed = Tape::new();
+        for (steps, expected) in [(9, 0), (10, 'a' as u16), (30, 130), (40, 0)] {
+            player.play_steps(steps, &mut replayed);
+            assert_eq!(replayed.kbd(), expected);
+        }
+        Ok(())
+    }
+}
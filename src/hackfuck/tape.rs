@@ -1,14 +1,14 @@
 use super::consts::*;
-use super::utils::{cell_to_bin, pause};
+use super::idiom::Idiom;
+use super::screen::Screen;
+use super::utils::pause;
+use crate::traits::prelude::{m_pos, pos};
 
 use anyhow::{bail, Result};
-use sdl2::keyboard::Keycode;
-use sdl2::rect::Point;
 
 /// Data structure to represent brainfuck memory tape
 pub struct Tape {
-    pub io_write: bool, // Flag indicating if most recent instruction updated screen
-    mem_ptr: usize,     // Memory pointer
+    mem_ptr: usize,              // Memory pointer
     mem_buffer: [u8; TAPE_SIZE], // Memory buffer
 }
 
@@ -55,13 +55,11 @@ impl Tape {
     /// Returns Err if memory pointer overflows
     pub fn inc_cell(&mut self, batch_size: usize, mem_ptr_offset: isize) -> Result<()> {
         if (self.mem_ptr as isize + mem_ptr_offset) < 0
-            || (self.mem_ptr as isize + mem_ptr_offset) > TAPE_SIZE as isize
+            || (self.mem_ptr as isize + mem_ptr_offset) >= TAPE_SIZE as isize
         {
             bail!("Memory pointer out of bounds")
         }
 
-        self.io_write = (self.mem_ptr as isize + mem_ptr_offset) as usize >= REGISTER_BUFFER + RAM;
-
         self.mem_buffer[(self.mem_ptr as isize + mem_ptr_offset) as usize] = self.mem_buffer
             [(self.mem_ptr as isize + mem_ptr_offset) as usize]
             .wrapping_add(batch_size as u8);
@@ -78,19 +76,208 @@ impl Tape {
     /// Returns Err if memory pointer overflows
     pub fn dec_cell(&mut self, batch_size: usize, mem_ptr_offset: isize) -> Result<()> {
         if (self.mem_ptr as isize + mem_ptr_offset) < 0
-            || (self.mem_ptr as isize + mem_ptr_offset) > TAPE_SIZE as isize
+            || (self.mem_ptr as isize + mem_ptr_offset) >= TAPE_SIZE as isize
         {
             bail!("Memory pointer out of bounds")
         }
 
-        self.io_write = (self.mem_ptr as isize + mem_ptr_offset) as usize >= REGISTER_BUFFER + RAM;
-
         self.mem_buffer[(self.mem_ptr as isize + mem_ptr_offset) as usize] = self.mem_buffer
             [(self.mem_ptr as isize + mem_ptr_offset) as usize]
             .wrapping_sub(batch_size as u8);
         Ok(())
     }
 
+    /// Moves the memory pointer by a fixed stride until it lands on a zero cell
+    ///
+    /// # Arguments
+    /// * `stride` - Number of cells moved per step, negative to scan left
+    ///
+    /// # Returns
+    /// Returns Err if no zero cell is found before the memory pointer leaves the tape
+    pub fn scan(&mut self, stride: isize) -> Result<()> {
+        // The pointer may rest right after the last cell, which can't be read
+        if self.mem_ptr >= TAPE_SIZE {
+            bail!("Memory pointer out of bounds")
+        }
+        let step = stride.unsigned_abs();
+
+        let distance = if stride >= 0 {
+            self.mem_buffer[self.mem_ptr..]
+                .iter()
+                .step_by(step)
+                .position(|&cell| cell == 0)
+        } else {
+            self.mem_buffer[..=self.mem_ptr]
+                .iter()
+                .rev()
+                .step_by(step)
+                .position(|&cell| cell == 0)
+        };
+
+        match distance {
+            Some(distance) if stride >= 0 => self.mem_ptr += distance * step,
+            Some(distance) => self.mem_ptr -= distance * step,
+            None if stride >= 0 => bail!("Memory pointer overflow"),
+            None => bail!("Memory pointer underflow"),
+        }
+        Ok(())
+    }
+
+    /// Determines if every cell in a range around the memory pointer is on the tape
+    ///
+    /// # Arguments
+    /// * `min_offset` - Lowest offset from current memory cell
+    /// * `max_offset` - Highest offset from current memory cell
+    pub fn in_bounds(&self, min_offset: isize, max_offset: isize) -> bool {
+        self.mem_ptr as isize + min_offset >= 0
+            && self.mem_ptr as isize + max_offset < TAPE_SIZE as isize
+    }
+
+    /// Increments cell value without checking the memory pointer, see `Tape::in_bounds`
+    ///
+    /// # Arguments
+    /// * `batch_size` - Value to increment cell by
+    /// * `mem_ptr_offset` - Offset from current memory cell
+    pub fn inc_cell_unchecked(&mut self, batch_size: usize, mem_ptr_offset: isize) {
+        let cell = (self.mem_ptr as isize + mem_ptr_offset) as usize;
+
+        self.mem_buffer[cell] = self.mem_buffer[cell].wrapping_add(batch_size as u8);
+    }
+
+    /// Decrements cell value without checking the memory pointer, see `Tape::in_bounds`
+    ///
+    /// # Arguments
+    /// * `batch_size` - Value to decrement cell by
+    /// * `mem_ptr_offset` - Offset from current memory cell
+    pub fn dec_cell_unchecked(&mut self, batch_size: usize, mem_ptr_offset: isize) {
+        let cell = (self.mem_ptr as isize + mem_ptr_offset) as usize;
+
+        self.mem_buffer[cell] = self.mem_buffer[cell].wrapping_sub(batch_size as u8);
+    }
+
+    /// Performs a recognized idiom directly on the tape
+    ///
+    /// # Arguments
+    /// * `idiom` - Operation to perform
+    ///
+    /// # Returns
+    /// Returns false without changing the tape if the idiom's preconditions don't hold,
+    /// the instructions it was recognized from have to run instead
+    pub fn idiom(&mut self, idiom: Idiom) -> Result<bool> {
+        match idiom {
+            Idiom::IncWord { word, temps } => {
+                let Some([upper, lower, temp0, temp1]) =
+                    self.cells([word.0, word.1, temps.0, temps.1])
+                else {
+                    return Ok(false);
+                };
+                if self.mem_buffer[temp0] != 0 || self.mem_buffer[temp1] != 0 {
+                    return Ok(false);
+                }
+
+                self.mem_buffer[lower] = self.mem_buffer[lower].wrapping_add(1);
+                if self.mem_buffer[lower] == 0 {
+                    self.mem_buffer[upper] = self.mem_buffer[upper].wrapping_add(1);
+                }
+            }
+            Idiom::DecWord { word, temps } => {
+                let Some([upper, lower, temp0, temp1]) =
+                    self.cells([word.0, word.1, temps.0, temps.1])
+                else {
+                    return Ok(false);
+                };
+                if self.mem_buffer[temp0] != 0 || self.mem_buffer[temp1] != 0 {
+                    return Ok(false);
+                }
+
+                if self.mem_buffer[lower] == 0 {
+                    self.mem_buffer[upper] = self.mem_buffer[upper].wrapping_sub(1);
+                }
+                self.mem_buffer[lower] = self.mem_buffer[lower].wrapping_sub(1);
+            }
+            Idiom::CopyWord { src, dest, temp } => {
+                let Some([src_upper, src_lower, dest_upper, dest_lower, temp]) =
+                    self.cells([src.0, src.1, dest.0, dest.1, temp])
+                else {
+                    return Ok(false);
+                };
+                if self.mem_buffer[temp] != 0 {
+                    return Ok(false);
+                }
+
+                self.mem_buffer[dest_upper] =
+                    self.mem_buffer[dest_upper].wrapping_add(self.mem_buffer[src_upper]);
+                self.mem_buffer[dest_lower] =
+                    self.mem_buffer[dest_lower].wrapping_add(self.mem_buffer[src_lower]);
+            }
+            Idiom::IsNonzero { word, dest, temps } => {
+                let Some([upper, lower, dest, temp0, temp1]) =
+                    self.cells([word.0, word.1, dest, temps.0, temps.1])
+                else {
+                    return Ok(false);
+                };
+                if [dest, temp0, temp1].iter().any(|&cell| self.mem_buffer[cell] != 0) {
+                    return Ok(false);
+                }
+
+                // Each nonzero byte adds one
+                self.mem_buffer[dest] =
+                    (self.mem_buffer[upper] != 0) as u8 + (self.mem_buffer[lower] != 0) as u8;
+            }
+            Idiom::ReadMemory { base } => {
+                let Some([base]) = self.cells([base]) else {
+                    return Ok(false);
+                };
+                let at = |pos: isize| (base as isize + pos - pos::AU) as usize;
+
+                let address = 256 * self.mem_buffer[at(pos::AU)] as usize
+                    + self.mem_buffer[at(pos::AL)] as usize;
+                // Gap cell after the addressed word, where the forward traversal stops
+                let head = at(pos::G0) + 3 * address;
+                if head >= TAPE_SIZE {
+                    return Ok(false);
+                }
+
+                // Scratch registers and every traversed gap cell must be clear,
+                // the backward traversal stops on T4
+                let registers = [pos::T4, pos::T5, pos::T6, pos::VU, pos::T7, pos::T8];
+                if registers.iter().any(|&pos| self.mem_buffer[at(pos)] != 0)
+                    || self.mem_buffer[at(pos::G0)..=head]
+                        .iter()
+                        .step_by(3)
+                        .any(|&cell| cell != 0)
+                {
+                    return Ok(false);
+                }
+
+                let word = (
+                    self.mem_buffer[(head as isize + m_pos::MU) as usize],
+                    self.mem_buffer[(head as isize + m_pos::ML) as usize],
+                );
+                self.mem_buffer[at(pos::MU)] = self.mem_buffer[at(pos::MU)].wrapping_add(word.0);
+                self.mem_buffer[at(pos::ML)] = self.mem_buffer[at(pos::ML)].wrapping_add(word.1);
+                self.mem_ptr = at(pos::T4);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Resolves cell offsets to memory buffer indices
+    ///
+    /// # Returns
+    /// Returns None if any cell is out of bounds
+    fn cells<const N: usize>(&self, offsets: [i16; N]) -> Option<[usize; N]> {
+        let mut cells = [0; N];
+        for (cell, offset) in cells.iter_mut().zip(offsets) {
+            let index = self.mem_ptr as isize + offset as isize;
+            if index < 0 || index >= TAPE_SIZE as isize {
+                return None;
+            }
+            *cell = index as usize;
+        }
+        Some(cells)
+    }
+
     /// Pauses program execution and prints debug interface
     pub fn breakpoint(&self) {
         // TODO: Update the mem layout, add additional registers
@@ -193,9 +380,44 @@ impl Tape {
         Self::default()
     }
 
-    /// Returns the current cell value
-    pub fn get_cell(&self) -> u8 {
-        self.mem_buffer[self.mem_ptr]
+    /// Returns a cell value
+    ///
+    /// # Arguments
+    /// * `mem_ptr_offset` - Offset from current memory cell
+    ///
+    /// # Returns
+    /// Returns Err if memory pointer out of bounds
+    pub fn get_cell(&self, mem_ptr_offset: isize) -> Result<u8> {
+        if (self.mem_ptr as isize + mem_ptr_offset) < 0
+            || (self.mem_ptr as isize + mem_ptr_offset) >= TAPE_SIZE as isize
+        {
+            bail!("Memory pointer out of bounds")
+        }
+
+        Ok(self.mem_buffer[(self.mem_ptr as isize + mem_ptr_offset) as usize])
+    }
+
+    /// Returns a cell value without checking the memory pointer, see `Tape::in_bounds`
+    ///
+    /// # Arguments
+    /// * `mem_ptr_offset` - Offset from current memory cell
+    pub fn get_cell_unchecked(&self, mem_ptr_offset: isize) -> u8 {
+        self.mem_buffer[(self.mem_ptr as isize + mem_ptr_offset) as usize]
+    }
+
+    /// Returns the memory pointer
+    pub fn get_ptr(&self) -> usize {
+        self.mem_ptr
+    }
+
+    /// Moves the memory pointer to a cell
+    pub(super) fn set_ptr(&mut self, mem_ptr: usize) {
+        self.mem_ptr = mem_ptr;
+    }
+
+    /// Returns a pointer to the first cell, for code that accesses the tape directly
+    pub(super) fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.mem_buffer.as_mut_ptr()
     }
 
     /// Returns segment of memory buffer
@@ -207,151 +429,50 @@ impl Tape {
         Some(&self.mem_buffer[start..=end])
     }
 
-    /// Updates KBD using SDL2 keycodes
-    pub fn update_kbd(&mut self, keycode: Keycode) {
-        let key_val: u8 = match keycode {
-            Keycode::Backspace => 129,
-            Keycode::Return => 128,
-            Keycode::Escape => 140,
-            Keycode::Space => 32,
-            Keycode::Exclaim => 33,
-            Keycode::Quotedbl => 34,
-            Keycode::Hash => 35,
-            Keycode::Dollar => 36,
-            Keycode::Percent => 37,
-            Keycode::Ampersand => 38,
-            Keycode::Quote => 39,
-            Keycode::LeftParen => 40,
-            Keycode::RightParen => 41,
-            Keycode::Asterisk => 42,
-            Keycode::Plus => 43,
-            Keycode::Comma => 44,
-            Keycode::Minus => 45,
-            Keycode::Period => 46,
-            Keycode::Slash => 47,
-            Keycode::Num0 => 48,
-            Keycode::Num1 => 49,
-            Keycode::Num2 => 50,
-            Keycode::Num3 => 51,
-            Keycode::Num4 => 52,
-            Keycode::Num5 => 53,
-            Keycode::Num6 => 54,
-            Keycode::Num7 => 55,
-            Keycode::Num8 => 56,
-            Keycode::Num9 => 57,
-            Keycode::Colon => 58,
-            Keycode::Semicolon => 59,
-            Keycode::Less => 60,
-            Keycode::Equals => 61,
-            Keycode::Greater => 62,
-            Keycode::Question => 63,
-            Keycode::At => 64,
-            Keycode::LeftBracket => 91,
-            Keycode::Backslash => 92,
-            Keycode::RightBracket => 93,
-            Keycode::Caret => 94,
-            Keycode::Underscore => 95,
-            Keycode::Backquote => 96,
-            Keycode::A => 65,
-            Keycode::B => 66,
-            Keycode::C => 67,
-            Keycode::D => 68,
-            Keycode::E => 69,
-            Keycode::F => 70,
-            Keycode::G => 71,
-            Keycode::H => 72,
-            Keycode::I => 73,
-            Keycode::J => 74,
-            Keycode::K => 75,
-            Keycode::L => 76,
-            Keycode::M => 77,
-            Keycode::N => 78,
-            Keycode::O => 79,
-            Keycode::P => 80,
-            Keycode::Q => 81,
-            Keycode::R => 82,
-            Keycode::S => 83,
-            Keycode::T => 84,
-            Keycode::U => 85,
-            Keycode::V => 86,
-            Keycode::W => 87,
-            Keycode::X => 88,
-            Keycode::Y => 89,
-            Keycode::Z => 90,
-            Keycode::Delete => 127,
-            Keycode::F1 => 141,
-            Keycode::F2 => 142,
-            Keycode::F3 => 143,
-            Keycode::F4 => 144,
-            Keycode::F5 => 145,
-            Keycode::F6 => 146,
-            Keycode::F7 => 147,
-            Keycode::F8 => 148,
-            Keycode::F9 => 149,
-            Keycode::F10 => 150,
-            Keycode::F11 => 151,
-            Keycode::F12 => 152,
-            Keycode::Insert => 138,
-            Keycode::Home => 134,
-            Keycode::PageUp => 136,
-            Keycode::End => 135,
-            Keycode::PageDown => 137,
-            Keycode::Right => 132,
-            Keycode::Left => 130,
-            Keycode::Down => 133,
-            Keycode::Up => 131,
-            // Keycode::LCtrl => todo!(),
-            // Keycode::LShift => todo!(),
-            // Keycode::LAlt => todo!(),
-            // Keycode::RCtrl => todo!(),
-            // Keycode::RShift => todo!(),
-            // Keycode::RAlt => todo!(),
-            _ => 0,
-        };
-
-        self.mem_buffer[REGISTER_BUFFER + RAM + SCREEN + 1] = key_val
+    /// Returns a word of the Hack RAM, screen or keyboard
+    ///
+    /// # Arguments
+    /// * `address` - Address of the word in the Hack memory map
+    ///
+    /// # Returns
+    /// Returns None if the address is past the keyboard
+    pub fn get_word(&self, address: usize) -> Option<u16> {
+        let upper = REGISTER_BUFFER + 3 * address;
+        if upper + 1 >= TAPE_SIZE {
+            return None;
+        }
+        Some(u16::from_be_bytes([
+            self.mem_buffer[upper],
+            self.mem_buffer[upper + 1],
+        ]))
     }
 
-    /// Creates vector of pixels to be drawn based of the screen buffer
-    pub fn get_pixels(&mut self) -> Vec<Point> {
-        let mut pixels = vec![];
-
-        let mut x = 0;
-        let mut y = 0;
-
-        for chunks in
-            self.mem_buffer[(REGISTER_BUFFER + RAM)..(REGISTER_BUFFER + RAM + SCREEN)].chunks(3)
-        {
-            let (w1, w2) = (chunks[0], chunks[1]);
-
-            for bit in cell_to_bin(w1) {
-                if bit {
-                    pixels.push(Point::new(x, y))
-                }
-                x += 1;
-            }
-            for bit in cell_to_bin(w2) {
-                if bit {
-                    pixels.push(Point::new(x, y))
-                }
-                x += 1;
-            }
+    /// Returns a view of the screen region
+    pub fn screen(&self) -> Screen<'_> {
+        Screen::new(&self.mem_buffer[(REGISTER_BUFFER + RAM)..(REGISTER_BUFFER + RAM + SCREEN)])
+    }
 
-            if x % 512 == 0 {
-                y += 1;
-                x = 0;
-            }
-        }
+    /// Returns the Hack code of the key being pressed, 0 when no key is pressed
+    pub fn kbd(&self) -> u16 {
+        u16::from_be_bytes([
+            self.mem_buffer[REGISTER_BUFFER + RAM + SCREEN],
+            self.mem_buffer[REGISTER_BUFFER + RAM + SCREEN + 1],
+        ])
+    }
 
-        pixels
+    /// Sets KBD to the Hack code of the key being pressed, 0 when no key is pressed
+    pub fn set_kbd(&mut self, key: u16) {
+        let [upper, lower] = key.to_be_bytes();
+        self.mem_buffer[REGISTER_BUFFER + RAM + SCREEN] = upper;
+        self.mem_buffer[REGISTER_BUFFER + RAM + SCREEN + 1] = lower;
     }
+
 }
 
 impl Default for Tape {
     /// Default values for a tape
     fn default() -> Self {
         Self {
-            io_write: false,
             mem_ptr: 0,
             mem_buffer: [0; TAPE_SIZE],
         }
@@ -404,10 +525,10 @@ mod tests {
     fn inc_cell() {
         let mut tape = Tape::new();
         tape.inc_cell(1, 0).unwrap();
-        assert_eq!(tape.get_cell(), 1);
+        assert_eq!(tape.get_cell(0).unwrap(), 1);
 
         tape.inc_cell(10, 0).unwrap();
-        assert_eq!(tape.get_cell(), 11);
+        assert_eq!(tape.get_cell(0).unwrap(), 11);
 
         tape.inc_cell(100, 1).unwrap();
         assert_eq!(tape.mem_buffer[tape.mem_ptr + 1], 100);
@@ -416,15 +537,64 @@ mod tests {
         assert_eq!(tape.mem_buffer[tape.mem_ptr + 2], 1);
     }
 
+    #[test]
+    fn in_bounds() {
+        let mut tape = Tape::new();
+        assert!(tape.in_bounds(0, TAPE_SIZE as isize - 1));
+        assert!(!tape.in_bounds(-1, 0));
+        assert!(!tape.in_bounds(0, TAPE_SIZE as isize));
+
+        tape.inc_ptr(10).unwrap();
+        assert!(tape.in_bounds(-10, 10));
+        assert!(!tape.in_bounds(-11, 10));
+
+        tape.inc_cell_unchecked(3, -10);
+        tape.dec_cell_unchecked(1, -10);
+        assert_eq!(tape.get_cell_unchecked(-10), 2);
+        assert_eq!(tape.mem_buffer[0], 2);
+    }
+
+    #[test]
+    fn scan() {
+        let mut tape = Tape::new();
+        for cell in [0, 3, 6, 7] {
+            tape.inc_cell(1, cell).unwrap();
+        }
+        tape.scan(3).unwrap();
+        assert_eq!(tape.mem_ptr, 9);
+
+        tape.dec_ptr(2).unwrap();
+        tape.scan(-3).unwrap();
+        assert_eq!(tape.mem_ptr, 4);
+
+        tape.scan(-1).unwrap();
+        assert_eq!(tape.mem_ptr, 4);
+
+        // The pointer can move to the end of the tape, but no cell is read there
+        let mut tape = Tape::new();
+        tape.inc_ptr(TAPE_SIZE).unwrap();
+        assert!(tape.scan(-1).is_err());
+        assert!(tape.scan(1).is_err());
+        assert_eq!(tape.mem_ptr, TAPE_SIZE);
+    }
+
+    #[test]
+    #[should_panic]
+    fn scan_underflow() {
+        let mut tape = Tape::new();
+        tape.inc_cell(1, 0).unwrap();
+        tape.scan(-1).unwrap();
+    }
+
     #[test]
     fn dec_cell() {
         let mut tape = Tape::new();
         tape.inc_cell(10, 0).unwrap();
         tape.dec_cell(1, 0).unwrap();
-        assert_eq!(tape.get_cell(), 9);
+        assert_eq!(tape.get_cell(0).unwrap(), 9);
 
         tape.dec_cell(10, 0).unwrap();
-        assert_eq!(tape.get_cell(), 255);
+        assert_eq!(tape.get_cell(0).unwrap(), 255);
 
         tape.dec_cell(1, 1).unwrap();
         assert_eq!(tape.mem_buffer[tape.mem_ptr + 1], 255);
@@ -432,4 +602,21 @@ mod tests {
         tape.dec_cell(1, 2).unwrap();
         assert_eq!(tape.mem_buffer[tape.mem_ptr + 2], 255);
     }
+
+    #[test]
+    fn get_word() {
+        let mut tape = Tape::new();
+        tape.mem_buffer[REGISTER_BUFFER + 3] = 0x12;
+        tape.mem_buffer[REGISTER_BUFFER + 4] = 0x34;
+        assert_eq!(tape.get_word(0), Some(0));
+        assert_eq!(tape.get_word(1), Some(0x1234));
+
+        tape.set_kbd(140);
+        assert_eq!(tape.get_word(24576), Some(140));
+        assert_eq!(tape.get_word(24577), None);
+
+        tape.set_kbd(0x1234);
+        assert_eq!(tape.get_word(24576), Some(0x1234));
+        assert_eq!(tape.kbd(), 0x1234);
+    }
 }
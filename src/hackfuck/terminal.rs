@@ -0,0 +1,369 @@
+use super::frontend::Frontend;
+use super::screen::{Screen, HEIGHT, WIDTH};
+use super::tape::Tape;
+
+use anyhow::{bail, Result};
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{
+    self, DisableFocusChange, EnableFocusChange, Event, KeyCode, KeyEvent, KeyEventKind,
+    KeyModifiers, KeyboardEnhancementFlags, PopKeyboardEnhancementFlags,
+    PushKeyboardEnhancementFlags,
+};
+use crossterm::style::Print;
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use std::io::{self, BufWriter, Stdout, Write};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Time a key counts as held after it was pressed, without release events it lasts until the
+/// terminal repeats the key
+const PRESS_HOLD: Duration = Duration::from_millis(600);
+
+/// Time a key counts as held after the terminal repeated it
+const REPEAT_HOLD: Duration = Duration::from_millis(100);
+
+/// Characters drawing the Hack screen in the terminal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Glyphs {
+    /// Braille patterns of 2 by 4 pixels, the screen takes 256 columns and 64 rows
+    Braille,
+    /// Half blocks of 1 by 2 pixels, the screen takes 512 columns and 128 rows
+    HalfBlocks,
+}
+
+impl FromStr for Glyphs {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "braille" => Ok(Glyphs::Braille),
+            "half-blocks" => Ok(Glyphs::HalfBlocks),
+            _ => bail!("expected braille or half-blocks"),
+        }
+    }
+}
+
+impl Glyphs {
+    /// Draws the screen as lines of text, black pixels are drawn in the color of the text
+    fn render(self, screen: Screen) -> Vec<String> {
+        match self {
+            Glyphs::Braille => (0..HEIGHT)
+                .step_by(4)
+                .map(|top| {
+                    (0..WIDTH)
+                        .step_by(2)
+                        .map(|left| braille(screen, left, top))
+                        .collect()
+                })
+                .collect(),
+            Glyphs::HalfBlocks => (0..HEIGHT)
+                .step_by(2)
+                .map(|top| (0..WIDTH).map(|x| half_block(screen, x, top)).collect())
+                .collect(),
+        }
+    }
+}
+
+/// Returns the braille pattern of the 2 by 4 pixels from a top left corner
+fn braille(screen: Screen, left: usize, top: usize) -> char {
+    // Dots are numbered down the left column, down the right one, then along the bottom row
+    const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+    let mut pattern = 0;
+    for (dy, row) in DOTS.iter().enumerate() {
+        for (dx, dot) in row.iter().enumerate() {
+            if screen.get_pixel(left + dx, top + dy) {
+                pattern |= dot;
+            }
+        }
+    }
+    char::from_u32(0x2800 + pattern).expect("braille patterns are characters")
+}
+
+/// Returns the half block of the 1 by 2 pixels from a top pixel
+fn half_block(screen: Screen, x: usize, top: usize) -> char {
+    match (screen.get_pixel(x, top), screen.get_pixel(x, top + 1)) {
+        (true, true) => '█',
+        (true, false) => '▀',
+        (false, true) => '▄',
+        (false, false) => ' ',
+    }
+}
+
+/// Keys held down in the terminal, KBD reads the one pressed last
+///
+/// Most terminals only report key presses and their repeats, so keys count as released a moment
+/// after their last press unless the terminal reports releases too
+#[derive(Debug)]
+struct Keyboard {
+    /// Held keys, their Hack codes and when they count as released, in the order they were pressed
+    held: Vec<(KeyCode, u16, Option<Instant>)>,
+    /// Whether the terminal reports key releases
+    releases: bool,
+}
+
+impl Keyboard {
+    fn new(releases: bool) -> Self {
+        Self {
+            held: vec![],
+            releases,
+        }
+    }
+
+    /// Returns the value of KBD, 0 when no key is held
+    fn code(&self) -> u16 {
+        self.held.last().map_or(0, |&(_, code, _)| code)
+    }
+
+    /// Holds or releases a key
+    ///
+    /// # Returns
+    /// Returns whether the held keys changed
+    fn key_event(&mut self, key: KeyEvent, now: Instant) -> bool {
+        let Some(code) = hack_code(key.code) else {
+            return false;
+        };
+        match key.kind {
+            KeyEventKind::Press | KeyEventKind::Repeat => {
+                let repeat = self.held.iter().any(|&(held, _, _)| held == key.code);
+                if repeat && self.releases {
+                    return false;
+                }
+                let hold = if repeat { REPEAT_HOLD } else { PRESS_HOLD };
+                let release = (!self.releases).then(|| now + hold);
+                self.held.retain(|&(held, _, _)| held != key.code);
+                self.held.push((key.code, code, release));
+            }
+            KeyEventKind::Release => {
+                let count = self.held.len();
+                self.held.retain(|&(held, _, _)| held != key.code);
+                // Releasing shift first releases the unshifted character, which was never held
+                if self.held.len() == count && matches!(key.code, KeyCode::Char(_)) {
+                    self.held
+                        .retain(|&(held, _, _)| !matches!(held, KeyCode::Char(_)));
+                }
+            }
+        }
+        true
+    }
+
+    /// Releases the keys not pressed again in time
+    ///
+    /// # Returns
+    /// Returns whether the held keys changed
+    fn expire(&mut self, now: Instant) -> bool {
+        let count = self.held.len();
+        self.held
+            .retain(|&(_, _, release)| release.is_none_or(|release| now < release));
+        self.held.len() != count
+    }
+
+    /// Releases every key
+    fn clear(&mut self) {
+        self.held.clear();
+    }
+}
+
+/// Returns the Hack code of a key, printable keys give the character they type
+fn hack_code(code: KeyCode) -> Option<u16> {
+    let code = match code {
+        KeyCode::Enter => 128,
+        KeyCode::Backspace => 129,
+        KeyCode::Left => 130,
+        KeyCode::Up => 131,
+        KeyCode::Right => 132,
+        KeyCode::Down => 133,
+        KeyCode::Home => 134,
+        KeyCode::End => 135,
+        KeyCode::PageUp => 136,
+        KeyCode::PageDown => 137,
+        KeyCode::Insert => 138,
+        KeyCode::Delete => 139,
+        KeyCode::Esc => 140,
+        KeyCode::F(number @ 1..=12) => 140 + number as u16,
+        KeyCode::Char(character) if (' '..='~').contains(&character) => character as u16,
+        _ => return None,
+    };
+    Some(code)
+}
+
+/// Shows the screen in the terminal and reads the keyboard from it, in raw mode
+///
+/// The terminal is restored when this is dropped
+pub struct Terminal {
+    out: BufWriter<Stdout>,
+    glyphs: Glyphs,
+    /// Lines shown in the terminal, only the lines that changed are drawn again
+    drawn: Vec<String>,
+    keyboard: Keyboard,
+    /// Whether Ctrl+C was pressed
+    quit: bool,
+}
+
+impl Terminal {
+    /// Switches the terminal to raw mode and an alternate screen
+    pub fn open(glyphs: Glyphs) -> Result<Self> {
+        terminal::enable_raw_mode()?;
+        // Terminals following the kitty keyboard protocol report key releases
+        let releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        let mut terminal = Self {
+            out: BufWriter::new(io::stdout()),
+            glyphs,
+            drawn: vec![],
+            keyboard: Keyboard::new(releases),
+            quit: false,
+        };
+        execute!(
+            terminal.out,
+            EnterAlternateScreen,
+            Hide,
+            Clear(ClearType::All),
+            // Reports when the terminal loses focus, so held keys can be released
+            EnableFocusChange
+        )?;
+        if releases {
+            execute!(
+                terminal.out,
+                PushKeyboardEnhancementFlags(
+                    KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES
+                        | KeyboardEnhancementFlags::REPORT_EVENT_TYPES
+                        | KeyboardEnhancementFlags::REPORT_ALTERNATE_KEYS
+                )
+            )?;
+        }
+        Ok(terminal)
+    }
+}
+
+impl Frontend for Terminal {
+    /// Draws the lines of the screen that changed since the last call
+    ///
+    /// Lines and columns that don't fit in the terminal are left out
+    fn present(&mut self, screen: Screen) -> Result<()> {
+        let (columns, rows) = terminal::size()?;
+        let lines = self.glyphs.render(screen);
+        for (row, line) in lines.iter().enumerate().take(rows as usize) {
+            if self.drawn.get(row) != Some(line) {
+                let visible: String = line.chars().take(columns as usize).collect();
+                queue!(self.out, MoveTo(0, row as u16), Print(visible))?;
+            }
+        }
+        self.out.flush()?;
+        self.drawn = lines;
+        Ok(())
+    }
+
+    /// Handles pending events, KBD is only written when the keyboard changed
+    fn poll_input(&mut self, tape: &mut Tape) -> Result<()> {
+        let now = Instant::now();
+        let mut changed = false;
+        while event::poll(Duration::ZERO)? {
+            match event::read()? {
+                Event::Key(key)
+                    if key.code == KeyCode::Char('c')
+                        && key.modifiers.contains(KeyModifiers::CONTROL) =>
+                {
+                    self.quit = true;
+                    return Ok(());
+                }
+                Event::Key(key) => changed |= self.keyboard.key_event(key, now),
+                // Keys released in another window never send an event
+                Event::FocusLost => {
+                    self.keyboard.clear();
+                    changed = true;
+                }
+                Event::Resize(..) => {
+                    queue!(self.out, Clear(ClearType::All))?;
+                    self.drawn.clear();
+                }
+                _ => {}
+            }
+        }
+        changed |= self.keyboard.expire(now);
+        if changed {
+            tape.set_kbd(self.keyboard.code());
+        }
+        Ok(())
+    }
+
+    fn should_quit(&self) -> bool {
+        self.quit
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        if self.keyboard.releases {
+            let _ = execute!(self.out, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(self.out, DisableFocusChange, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hackfuck::consts::SCREEN;
+
+    #[test]
+    fn render() {
+        // Leftmost two pixels of the top row and the pixel below the first one
+        let mut cells = vec![0; SCREEN];
+        cells[1] = 0x03;
+        cells[3 * WIDTH / 16 + 1] = 0x01;
+        let screen = Screen::new(&cells);
+
+        let lines = Glyphs::Braille.render(screen);
+        assert_eq!(lines.len(), 64);
+        assert!(lines.iter().all(|line| line.chars().count() == 256));
+        assert!(lines[0].starts_with("\u{280b}\u{2800}"));
+
+        let lines = Glyphs::HalfBlocks.render(screen);
+        assert_eq!(lines.len(), 128);
+        assert!(lines.iter().all(|line| line.chars().count() == 512));
+        assert!(lines[0].starts_with("█▀ "));
+        assert!(lines[1].trim().is_empty());
+    }
+
+    #[test]
+    fn keyboard_without_releases() {
+        let mut keyboard = Keyboard::new(false);
+        let now = Instant::now();
+        let press = |code| KeyEvent::new(code, KeyModifiers::NONE);
+
+        assert!(keyboard.key_event(press(KeyCode::Char('a')), now));
+        assert_eq!(keyboard.code(), 'a' as u16);
+        assert!(!keyboard.expire(now + PRESS_HOLD / 2));
+
+        // Repeats keep the key held for a shorter time
+        keyboard.key_event(press(KeyCode::Char('a')), now + PRESS_HOLD / 2);
+        assert!(!keyboard.expire(now + PRESS_HOLD / 2 + REPEAT_HOLD / 2));
+        assert!(keyboard.expire(now + PRESS_HOLD / 2 + REPEAT_HOLD));
+        assert_eq!(keyboard.code(), 0);
+
+        keyboard.key_event(press(KeyCode::F(12)), now);
+        assert_eq!(keyboard.code(), 152);
+        assert!(!keyboard.key_event(press(KeyCode::Tab), now));
+        assert_eq!(keyboard.code(), 152);
+    }
+
+    #[test]
+    fn keyboard_with_releases() {
+        let mut keyboard = Keyboard::new(true);
+        let now = Instant::now();
+        let event = |code, kind| KeyEvent::new_with_kind(code, KeyModifiers::NONE, kind);
+
+        keyboard.key_event(event(KeyCode::Left, KeyEventKind::Press), now);
+        keyboard.key_event(event(KeyCode::Char('!'), KeyEventKind::Press), now);
+        assert!(!keyboard.expire(now + PRESS_HOLD * 10));
+        assert!(!keyboard.key_event(event(KeyCode::Char('!'), KeyEventKind::Repeat), now));
+        assert_eq!(keyboard.code(), '!' as u16);
+
+        // The character was typed with shift, which was released first
+        keyboard.key_event(event(KeyCode::Char('1'), KeyEventKind::Release), now);
+        assert_eq!(keyboard.code(), 130);
+        keyboard.key_event(event(KeyCode::Left, KeyEventKind::Release), now);
+        assert_eq!(keyboard.code(), 0);
+    }
+}
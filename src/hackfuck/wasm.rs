@@ -0,0 +1,303 @@
+use super::consts::TAPE_SIZE;
+use super::instruction::Instruction;
+
+use std::fmt::Write;
+
+/// Size of a page of WebAssembly memory in bytes
+const PAGE_SIZE: usize = 65536;
+
+/// Lowers a program into a WebAssembly text module
+///
+/// The tape is the start of the exported linear memory `memory`. The module exports
+/// `step(n)`, which runs the program until it jumped back to the start of a loop `n` more
+/// times and returns 1 once it finished, and `tape_ptr()`, which returns the memory pointer.
+/// Before `step` returns it calls the imported `env.present_screen`, so the host can draw the
+/// screen from memory.
+/// Idioms and guards are dropped, the code they were recognized from is kept.
+pub fn emit_wat(instructions: &[Instruction]) -> String {
+    let mut wat = Wat {
+        instructions,
+        code: String::new(),
+        depth: 0,
+    };
+
+    wat.line("(module");
+    wat.line("  (import \"env\" \"present_screen\" (func $present_screen))");
+    let pages = TAPE_SIZE.div_ceil(PAGE_SIZE);
+    wat.line(&format!("  (memory (export \"memory\") {pages})"));
+    wat.line("  (global $ptr (mut i32) (i32.const 0))");
+    wat.line("  ;; One more than the index of the loop to run again, 0 when starting over");
+    wat.line("  (global $resume (mut i32) (i32.const 0))");
+    wat.line("  (global $done (mut i32) (i32.const 0))");
+    wat.line("  (func (export \"tape_ptr\") (result i32)");
+    wat.line("    global.get $ptr)");
+    wat.line("  (func (export \"step\") (param $budget i32) (result i32)");
+    wat.line("    (local $p i32)");
+    wat.depth = 2;
+    wat.lines(&[
+        "global.get $done",
+        "if",
+        "i32.const 1",
+        "return",
+        "end",
+        "global.get $ptr",
+        "local.set $p",
+    ]);
+
+    wat.sequence(0, instructions.len());
+
+    wat.lines(&[
+        "i32.const 1",
+        "global.set $done",
+        "local.get $p",
+        "global.set $ptr",
+        "call $present_screen",
+        "i32.const 1))",
+    ]);
+    wat.code
+}
+
+/// Module text being generated
+struct Wat<'a> {
+    instructions: &'a [Instruction],
+    code: String,
+    /// Number of indentation levels of the next line
+    depth: usize,
+}
+
+impl Wat<'_> {
+    fn line(&mut self, line: &str) {
+        if line.starts_with("end") || line.starts_with("else") {
+            self.depth -= 1;
+        }
+        writeln!(self.code, "{}{line}", "  ".repeat(self.depth)).unwrap();
+        if ["block", "loop", "if", "else"]
+            .iter()
+            .any(|start| line.starts_with(start))
+        {
+            self.depth += 1;
+        }
+    }
+
+    fn lines(&mut self, lines: &[&str]) {
+        for line in lines {
+            self.line(line);
+        }
+    }
+
+    /// Lowers instructions with balanced loops, skipping them while resuming into a later loop
+    fn sequence(&mut self, start: usize, end: usize) {
+        let mut i = start;
+        while i < end {
+            if let Instruction::StartLoop(loop_end, _) = self.instructions[i] {
+                self.lower_loop(i, loop_end);
+                i = loop_end + 1;
+                continue;
+            }
+
+            let straight = (i..end)
+                .find(|&j| matches!(self.instructions[j], Instruction::StartLoop(_, _)))
+                .unwrap_or(end);
+            self.lines(&["global.get $resume", "i32.eqz", "if"]);
+            for j in i..straight {
+                self.instruction(self.instructions[j]);
+            }
+            self.line("end");
+            i = straight;
+        }
+    }
+
+    /// Lowers a loop, it can be resumed at the start of its body or inside of a nested loop
+    fn lower_loop(&mut self, start: usize, end: usize) {
+        let (Instruction::StartLoop(_, start_offset), Instruction::EndLoop(_, end_offset)) =
+            (self.instructions[start], self.instructions[end])
+        else {
+            unreachable!("Loops are paired");
+        };
+
+        self.line(&format!("block $exit{start}"));
+        self.lines(&["global.get $resume", "i32.eqz", "if"]);
+        self.load(start_offset);
+        self.lines(&["i32.eqz", &format!("br_if $exit{start}"), "else"]);
+        // Nested loops have indices between the start and the end of this loop
+        self.lines(&[
+            "global.get $resume",
+            &format!("i32.const {}", start + 1),
+            "i32.sub",
+            &format!("i32.const {}", end - start),
+            "i32.gt_u",
+            &format!("br_if $exit{start}"),
+            "end",
+        ]);
+
+        self.line(&format!("loop $body{start}"));
+        self.lines(&[
+            "global.get $resume",
+            &format!("i32.const {}", start + 1),
+            "i32.eq",
+            "if",
+            "i32.const 0",
+            "global.set $resume",
+            "end",
+        ]);
+        self.sequence(start + 1, end);
+
+        self.load(end_offset);
+        self.lines(&["i32.eqz", &format!("br_if $exit{start}")]);
+        self.lines(&[
+            "local.get $budget",
+            "i32.eqz",
+            "if",
+            &format!("i32.const {}", start + 1),
+            "global.set $resume",
+            "local.get $p",
+            "global.set $ptr",
+            "call $present_screen",
+            "i32.const 0",
+            "return",
+            "end",
+            "local.get $budget",
+            "i32.const 1",
+            "i32.sub",
+            "local.set $budget",
+            &format!("br $body{start}"),
+            "end",
+            "end",
+        ]);
+    }
+
+    /// Lowers an instruction outside of loops
+    fn instruction(&mut self, instruction: Instruction) {
+        match instruction {
+            Instruction::IncPtr(batch) => self.move_ptr("i32.add", batch),
+            Instruction::DecPtr(batch) => self.move_ptr("i32.sub", batch),
+            Instruction::IncCell(batch, offset) => self.update_cell("i32.add", batch, offset),
+            Instruction::DecCell(batch, offset) => self.update_cell("i32.sub", batch, offset),
+            Instruction::Scan(stride) => {
+                self.lines(&["block $found", "loop $scan"]);
+                self.load(0);
+                self.lines(&["i32.eqz", "br_if $found"]);
+                let op = if stride >= 0 { "i32.add" } else { "i32.sub" };
+                self.move_ptr(op, stride.unsigned_abs());
+                self.lines(&["br $scan", "end", "end"]);
+            }
+            Instruction::StartLoop(_, _) | Instruction::EndLoop(_, _) => {
+                unreachable!("Loops are lowered by Wat::lower_loop")
+            }
+            Instruction::Idiom(_, _) | Instruction::Guard(_, _, _) | Instruction::BreakPoint => {}
+        }
+    }
+
+    fn move_ptr(&mut self, op: &str, batch: usize) {
+        self.lines(&[
+            "local.get $p",
+            &format!("i32.const {}", batch as u32 as i32),
+            op,
+            "local.set $p",
+        ]);
+    }
+
+    /// Pushes the address of a cell and the offset to add to it in load and store instructions
+    fn address(&mut self, offset: isize) -> String {
+        self.line("local.get $p");
+        if offset >= 0 {
+            format!(" offset={offset}")
+        } else {
+            self.lines(&[&format!("i32.const {offset}"), "i32.add"]);
+            String::new()
+        }
+    }
+
+    fn load(&mut self, offset: isize) {
+        let memarg = self.address(offset);
+        self.line(&format!("i32.load8_u{memarg}"));
+    }
+
+    fn update_cell(&mut self, op: &str, batch: usize, offset: isize) {
+        let memarg = self.address(offset);
+        self.load(offset);
+        self.lines(&[&format!("i32.const {}", batch as u8), op]);
+        self.line(&format!("i32.store8{memarg}"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hackfuck::{interpreter::Interpreter, parser::Parser};
+    use crate::prelude::*;
+
+    use wasmi::{Caller, Engine, Linker, Module, Store};
+
+    /// Runs a program with `Interpreter::eval` and as a WebAssembly module, calling `step` with a
+    /// budget until it finishes, both must end in the same state
+    fn compare(program: &[u8], budget: i32) {
+        let instructions = Parser::from_bytes(program).unwrap().optimized_parse(false);
+        let mut interpreter = Interpreter::new(instructions.clone());
+        interpreter.eval().unwrap();
+
+        let binary = ::wat::parse_str(emit_wat(&instructions)).unwrap();
+        let engine = Engine::default();
+        let module = Module::new(&engine, &binary).unwrap();
+        // The store counts the calls of `present_screen`
+        let mut store = Store::new(&engine, 0usize);
+        let mut linker = Linker::new(&engine);
+        linker
+            .func_wrap("env", "present_screen", |mut caller: Caller<usize>| {
+                *caller.data_mut() += 1;
+            })
+            .unwrap();
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .and_then(|pre| pre.start(&mut store))
+            .unwrap();
+        let step = instance.get_typed_func::<i32, i32>(&store, "step").unwrap();
+        let tape_ptr = instance
+            .get_typed_func::<(), i32>(&store, "tape_ptr")
+            .unwrap();
+
+        let mut steps = 1;
+        while step.call(&mut store, budget).unwrap() == 0 {
+            steps += 1;
+        }
+
+        assert_eq!(*store.data(), steps);
+        assert_eq!(step.call(&mut store, budget).unwrap(), 1);
+        assert_eq!(
+            tape_ptr.call(&mut store, ()).unwrap() as usize,
+            interpreter.tape.get_ptr()
+        );
+        let memory = instance.get_memory(&store, "memory").unwrap();
+        assert_eq!(
+            &memory.data(&store)[..TAPE_SIZE],
+            interpreter.tape.get_slice(0, TAPE_SIZE - 1).unwrap()
+        );
+    }
+
+    #[test]
+    fn matches_eval() {
+        for budget in [0, 1, 7, i32::MAX] {
+            compare(b"+>++++++>->>>>>--------", budget);
+            compare(b"++++[->+++<]>[-<++>]", budget);
+            compare(b">>+++[>+>++<<-]>[-]>[>>>]", budget);
+            compare(b">>>>>>>>+[<<<]<<++", budget);
+            compare(b"++[>+[>+<-]<-]>>", budget);
+            compare(b"+++[>++[>+++[>+<-]<-]<-]+[[-]>]", budget);
+        }
+    }
+
+    #[test]
+    fn matches_eval_idioms() -> anyhow::Result<()> {
+        let mut coder = Coder::new(vec![]);
+        coder
+            .set_word(word::D, 0x00ff)?
+            .inc_word(word::D, [pos::VU, pos::VL])?
+            .copy_word(word::D, &[word::Q], pos::VU)?
+            .dec_word(word::Q, [pos::VU, pos::VL])?
+            .is_nonzero(word::Q, pos::FU, [pos::VU, pos::VL])?
+            .read_memory()?
+            .seek(0)?;
+        compare(coder.writer(), 100);
+        Ok(())
+    }
+}
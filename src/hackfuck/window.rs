@@ -0,0 +1,207 @@
+use super::frontend::Frontend;
+use super::keyboard::Keyboard;
+use super::screen::{Screen, HEIGHT, WIDTH};
+use super::tape::Tape;
+
+use anyhow::{bail, Context, Result};
+use sdl2::event::{Event, WindowEvent};
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::{Canvas, Texture};
+use sdl2::{video, EventPump, Sdl};
+use std::str::FromStr;
+
+/// Color of the pixels in the window, as red, green and blue components
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl Rgb {
+    pub const BLACK: Rgb = Rgb(0, 0, 0);
+    pub const WHITE: Rgb = Rgb(255, 255, 255);
+}
+
+impl FromStr for Rgb {
+    type Err = anyhow::Error;
+
+    /// Parses `black`, `white` or a hexadecimal color like `#33ff66`, the `#` being optional
+    fn from_str(color: &str) -> Result<Self> {
+        match color {
+            "black" => return Ok(Rgb::BLACK),
+            "white" => return Ok(Rgb::WHITE),
+            _ => {}
+        }
+        let hex = color.strip_prefix('#').unwrap_or(color);
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            bail!("expected black, white or a color like #33ff66");
+        }
+        let component = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16);
+        Ok(Rgb(component(0)?, component(2)?, component(4)?))
+    }
+}
+
+/// How the window shows the screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowOptions {
+    /// Number of window pixels per side of a Hack pixel
+    pub scale: u32,
+    /// Cover the whole display, the screen is scaled by a whole factor and centered
+    pub fullscreen: bool,
+    /// Color of black Hack pixels
+    pub foreground: Rgb,
+    /// Color of white Hack pixels
+    pub background: Rgb,
+}
+
+impl Default for WindowOptions {
+    fn default() -> Self {
+        Self {
+            scale: 1,
+            fullscreen: false,
+            foreground: Rgb::BLACK,
+            background: Rgb::WHITE,
+        }
+    }
+}
+
+/// Shows the screen in a window and reads the keyboard from it
+///
+/// The screen is drawn through a texture updated every frame
+pub struct Window {
+    // Textures are freed along with the canvas
+    texture: Texture,
+    canvas: Canvas<video::Window>,
+    event_pump: EventPump,
+    keyboard: Keyboard,
+    foreground: Rgb,
+    background: Rgb,
+    /// Whether the window was closed
+    quit: bool,
+    _sdl_context: Sdl,
+}
+
+impl Window {
+    /// Opens the window, its size is a whole multiple of the size of the screen
+    ///
+    /// # Arguments
+    /// * `options` - Size and colors of the window
+    pub fn open(options: &WindowOptions) -> Result<Self> {
+        let sdl_context = sdl2::init().map_err(anyhow::Error::msg)?;
+        let video = sdl_context.video().map_err(anyhow::Error::msg)?;
+        // Typed characters are read from text input events, with shift and the layout applied
+        video.text_input().start();
+
+        let scale = options.scale.max(1);
+        let mut window = video.window("BrainHack", WIDTH as u32 * scale, HEIGHT as u32 * scale);
+        window.position_centered();
+        if options.fullscreen {
+            window.fullscreen_desktop();
+        }
+        let window = window.build().context("cannot open the window")?;
+
+        let mut canvas = window
+            .into_canvas()
+            .build()
+            .context("cannot draw in the window")?;
+        // Whole scale factors keep every Hack pixel the same size, whatever the size of the window
+        canvas.set_logical_size(WIDTH as u32, HEIGHT as u32)?;
+        canvas.set_integer_scale(true).map_err(anyhow::Error::msg)?;
+
+        let texture = canvas
+            .texture_creator()
+            .create_texture_streaming(PixelFormatEnum::RGB24, WIDTH as u32, HEIGHT as u32)
+            .context("cannot create the screen texture")?;
+        let event_pump = sdl_context.event_pump().map_err(anyhow::Error::msg)?;
+        Ok(Self {
+            texture,
+            canvas,
+            event_pump,
+            keyboard: Keyboard::default(),
+            foreground: options.foreground,
+            background: options.background,
+            quit: false,
+            _sdl_context: sdl_context,
+        })
+    }
+}
+
+impl Frontend for Window {
+    fn present(&mut self, screen: Screen) -> Result<()> {
+        let (foreground, background) = (self.foreground, self.background);
+        self.texture
+            .with_lock(None, |buffer, pitch| {
+                for (y, row) in buffer.chunks_mut(pitch).take(HEIGHT).enumerate() {
+                    for (x, pixel) in row.chunks_mut(3).take(WIDTH).enumerate() {
+                        let Rgb(r, g, b) = if screen.get_pixel(x, y) {
+                            foreground
+                        } else {
+                            background
+                        };
+                        pixel.copy_from_slice(&[r, g, b]);
+                    }
+                }
+            })
+            .map_err(anyhow::Error::msg)?;
+
+        // Fullscreen windows show the background color around the screen
+        let Rgb(r, g, b) = self.background;
+        self.canvas.set_draw_color((r, g, b));
+        self.canvas.clear();
+        self.canvas
+            .copy(&self.texture, None, None)
+            .map_err(anyhow::Error::msg)?;
+        self.canvas.present();
+        Ok(())
+    }
+
+    /// Handles pending events, KBD is set to the key held down last
+    fn poll_input(&mut self, tape: &mut Tape) -> Result<()> {
+        let mut changed = false;
+        for event in self.event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } => {
+                    self.quit = true;
+                    return Ok(());
+                }
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    ..
+                } => self.keyboard.key_down(keycode),
+                Event::KeyUp {
+                    keycode: Some(keycode),
+                    ..
+                } => self.keyboard.key_up(keycode),
+                Event::TextInput { text, .. } => self.keyboard.text_input(&text),
+                // Keys released in another window never send an event
+                Event::Window {
+                    win_event: WindowEvent::FocusLost,
+                    ..
+                } => self.keyboard.clear(),
+                _ => continue,
+            }
+            changed = true;
+        }
+        if changed {
+            tape.set_kbd(self.keyboard.code());
+        }
+        Ok(())
+    }
+
+    fn should_quit(&self) -> bool {
+        self.quit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_rgb() -> Result<()> {
+        assert_eq!("#33ff66".parse::<Rgb>()?, Rgb(0x33, 0xff, 0x66));
+        assert_eq!("0A0b0C".parse::<Rgb>()?, Rgb(10, 11, 12));
+        assert_eq!("white".parse::<Rgb>()?, Rgb::WHITE);
+        assert!("#33ff6".parse::<Rgb>().is_err());
+        assert!("#33ff6g".parse::<Rgb>().is_err());
+        assert!("+33ff66".parse::<Rgb>().is_err());
+        Ok(())
+    }
+}
@@ -1,3 +1,5 @@
+pub mod assembler;
+pub mod cli;
 pub mod coder;
 pub mod hackfuck;
 pub mod traits;